@@ -56,6 +56,28 @@ impl TokenTemplate {
     }
 }
 
+/// `signer` signs for the DID owner `did` of a data id with the key at `key_index` of the DID,
+/// the identity contract decides whether the key belongs to the signer
+#[derive(Encoder, Decoder)]
+pub struct DidSigner {
+    pub did: Vec<u8>,
+    pub signer: Address,
+    pub key_index: u32,
+}
+
+/// the signers of DID owners are optional trailing arguments of the methods which need owner signatures
+///
+/// no argument left means no signer, an argument which does not decode fails
+pub fn read_did_signers(source: &mut Source) -> Vec<DidSigner> {
+    let len = match source.read_varuint() {
+        Ok(len) => len,
+        Err(_) => return vec![],
+    };
+    (0..len)
+        .map(|_| source.read().expect("malformed did signers"))
+        .collect()
+}
+
 #[derive(Encoder, Decoder, Clone)]
 pub struct Fee {
     pub contract_addr: Address,
@@ -73,7 +95,40 @@ impl Fee {
     }
 }
 
-#[derive(Clone)]
+impl Fee {
+    pub fn currency(&self) -> Currency {
+        Currency {
+            contract_addr: self.contract_addr.clone(),
+            contract_type: self.contract_type.clone(),
+        }
+    }
+}
+
+/// the token a fee is paid in, ONT and ONG are equal whatever contract_addr says
+#[derive(Encoder, Decoder, Clone)]
+pub struct Currency {
+    pub contract_addr: Address,
+    pub contract_type: TokenType,
+}
+
+impl Currency {
+    /// bytes used to build storage keys, contract_addr is only meaningful for OEP4
+    pub fn to_key_bytes(&self) -> Vec<u8> {
+        match self.contract_type {
+            TokenType::ONT => vec![0u8],
+            TokenType::ONG => vec![1u8],
+            TokenType::OEP4 => [&[2u8][..], self.contract_addr.as_ref()].concat(),
+        }
+    }
+}
+
+impl PartialEq for Currency {
+    fn eq(&self, other: &Self) -> bool {
+        self.to_key_bytes() == other.to_key_bytes()
+    }
+}
+
+#[derive(Clone, PartialEq)]
 pub enum TokenType {
     ONT,
     ONG,
@@ -38,3 +38,86 @@ fn test_fee() {
     let fee2: Fee = source.read().unwrap();
     assert_eq!(fee.count, fee2.count);
 }
+
+#[test]
+fn test_currency_key() {
+    let fee = Fee {
+        contract_addr: Address::repeat_byte(1),
+        contract_type: TokenType::ONG,
+        count: 10,
+    };
+    let ong = Fee {
+        contract_addr: Address::repeat_byte(2),
+        ..fee.clone()
+    };
+    assert_eq!(fee.currency().to_key_bytes(), ong.currency().to_key_bytes());
+
+    let oep4 = Fee {
+        contract_type: TokenType::OEP4,
+        ..fee.clone()
+    };
+    let oep4_2 = Fee {
+        contract_addr: Address::repeat_byte(2),
+        ..oep4.clone()
+    };
+    assert_ne!(
+        oep4.currency().to_key_bytes(),
+        oep4_2.currency().to_key_bytes()
+    );
+}
+
+#[test]
+fn test_currency_eq() {
+    let ong = Currency {
+        contract_addr: Address::new([0u8; 20]),
+        contract_type: TokenType::ONG,
+    };
+    let alias = Currency {
+        contract_addr: Address::repeat_byte(1),
+        contract_type: TokenType::ONG,
+    };
+    assert!(ong == alias);
+    let oep4 = Currency {
+        contract_addr: Address::repeat_byte(1),
+        contract_type: TokenType::OEP4,
+    };
+    let other = Currency {
+        contract_addr: Address::repeat_byte(2),
+        contract_type: TokenType::OEP4,
+    };
+    assert!(oep4 != other);
+    assert!(oep4 != alias);
+}
+
+#[test]
+fn read_did_signers_at_the_end() {
+    let mut sink = Sink::new(16);
+    sink.write(b"data1".as_ref());
+    let mut source = Source::new(sink.bytes());
+    let _data_id: &[u8] = source.read().unwrap();
+    assert!(read_did_signers(&mut source).is_empty());
+}
+
+#[test]
+#[should_panic(expected = "malformed did signers")]
+fn read_malformed_did_signers() {
+    let mut source = Source::new(&[1u8, 5]);
+    read_did_signers(&mut source);
+}
+
+#[test]
+fn read_many_did_signers() {
+    let signers: Vec<DidSigner> = (0..300u32)
+        .map(|i| DidSigner {
+            did: b"did:ont:alice".to_vec(),
+            signer: Address::repeat_byte(1),
+            key_index: i,
+        })
+        .collect();
+    let mut sink = Sink::new(16);
+    sink.write(&signers);
+    let mut source = Source::new(sink.bytes());
+    let read = read_did_signers(&mut source);
+    assert_eq!(read.len(), 300);
+    assert_eq!(read[299].key_index, 299);
+}
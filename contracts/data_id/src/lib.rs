@@ -1,84 +1,912 @@
 #![cfg_attr(not(feature = "mock"), no_std)]
 #![feature(proc_macro_hygiene)]
+extern crate alloc;
 extern crate ontio_std as ostd;
-use ostd::abi::{Decoder, Encoder, EventBuilder, Sink, Source};
+use alloc::collections::btree_map::BTreeMap;
+use alloc::collections::btree_set::BTreeSet;
+use ostd::abi::{Decoder, Encoder, Error, EventBuilder, Sink, Source};
+use ostd::contract::wasm;
 use ostd::database;
 use ostd::prelude::*;
-use ostd::runtime::{check_witness, input, ret};
+use ostd::runtime::{check_witness, input, ret, timestamp};
 use ostd::types::H256;
 extern crate common;
-use common::RT;
+use common::{read_did_signers, DidSigner, TokenTemplate, RT};
+
+#[cfg(test)]
+mod test;
 
 const KEY_DATA_ID: &[u8] = b"01";
+const KEY_DATA_VERSIONS: &[u8] = b"02";
+const KEY_ADMIN: &[u8] = b"03";
+const KEY_DTOKEN_CONTRACT: &[u8] = b"04";
+const KEY_CHILDREN: &[u8] = b"05";
+const KEY_REVOKED: &[u8] = b"06";
+const KEY_FROZEN: &[u8] = b"07";
+const KEY_OWNER_DATA_IDS: &[u8] = b"08";
+const KEY_IDENTITY_CONTRACT: &[u8] = b"09";
+const KEY_DID_DATA_IDS: &[u8] = b"10";
+const KEY_CHILD_COUNT: &[u8] = b"11";
+const KEY_OWNER_INDEX_COUNT: &[u8] = b"12";
+const KEY_OWNER_INDEX_POSITION: &[u8] = b"13";
+
+const MAX_PAGE_SIZE: u32 = 100;
+const MAX_LINEAGE_SIZE: u32 = 100;
+
+const ADMIN: Address = ostd::macros::base58!("AYnhakv7kC9R5ppw65JoE2rt6xDzCjCTvD");
 
-#[derive(Encoder, Decoder)]
 pub struct DataIdInfo {
-    data_id: Vec<u8>,     //used to uniquely mark a piece of data
-    data_type: RT,        // data type, contains static data type and other type.
+    data_id: Vec<u8>,         //used to uniquely mark a piece of data
+    data_type: RT,            // data type, contains static data type and other type.
     data_meta_hash: H256, //data meta is meta information of data, data meta hash is the sha256 of data meta
     data_hash: H256,      //data hash is sha256 of data
     owners: Vec<Address>, // data owner
+    threshold: u32,       // number of owner signatures needed to act on the data id
+    version: u32,         // version of data_hash and data_meta_hash, starts from 1
+    parents: Vec<Vec<u8>>, // the data ids this data is derived from
+    did_owners: Vec<Vec<u8>>, // ONT IDs owning the data, verified through the identity contract
+}
+
+impl Encoder for DataIdInfo {
+    fn encode(&self, sink: &mut Sink) {
+        sink.write(&self.data_id);
+        sink.write(&self.data_type);
+        sink.write(&self.data_meta_hash);
+        sink.write(&self.data_hash);
+        sink.write(&self.owners);
+        sink.write(self.threshold);
+        sink.write(self.version);
+        sink.write(&self.parents);
+        sink.write(&self.did_owners);
+    }
+}
+
+impl<'a> Decoder<'a> for DataIdInfo {
+    fn decode(source: &mut Source<'a>) -> Result<Self, Error> {
+        let data_id = source.read()?;
+        let data_type = source.read()?;
+        let data_meta_hash = source.read()?;
+        let data_hash = source.read()?;
+        let owners = source.read()?;
+        // a data id registered before the threshold was added only has the fields above,
+        // any one of its owners can act on it
+        let threshold = match source.read() {
+            Ok(threshold) => threshold,
+            Err(_) => {
+                return Ok(DataIdInfo {
+                    data_id,
+                    data_type,
+                    data_meta_hash,
+                    data_hash,
+                    owners,
+                    threshold: 1,
+                    version: 1,
+                    parents: vec![],
+                    did_owners: vec![],
+                })
+            }
+        };
+        Ok(DataIdInfo {
+            data_id,
+            data_type,
+            data_meta_hash,
+            data_hash,
+            owners,
+            threshold,
+            version: source.read()?,
+            parents: source.read()?,
+            did_owners: source.read()?,
+        })
+    }
+}
+
+/// proof that `holder` bought a dtoken of the parent data id, used instead of the parent owners consent
+#[derive(Encoder, Decoder)]
+pub struct ParentProof {
+    parent: Vec<u8>,
+    resource_id: Vec<u8>,
+    token_template: TokenTemplate, // data_id of the template must be the parent
+    holder: Address, // one of the owners of the derived data id, or the signer of one of its DID owners
+}
+
+/// the content hashes of one version of a data id
+#[derive(Encoder, Decoder, Clone)]
+pub struct DataVersion {
+    version: u32,
+    data_hash: H256,
+    data_meta_hash: H256,
+    published_at: u64,
+}
+
+/// why and when the owners retired the data id
+#[derive(Encoder, Decoder)]
+pub struct Revocation {
+    reason_hash: H256, // sha256 of the reason, e.g. an erasure request
+    revoked_at: u64,
+}
+
+#[derive(Encoder, Decoder)]
+pub struct OwnerSet {
+    threshold: u32,
+    owners: Vec<Address>,
+    did_owners: Vec<Vec<u8>>,
 }
 
 impl DataIdInfo {
-    fn default() -> Self {
-        DataIdInfo {
-            data_id: vec![0u8],
-            data_type: RT::Other,
-            data_meta_hash: H256::new([0u8; 32]),
-            data_hash: H256::new([0u8; 32]),
-            owners: vec![],
-        }
+    /// number of owners who signed, a DID owner signs through `did_signers`
+    fn count_witnesses(&self, did_signers: &[DidSigner]) -> u32 {
+        let signed = self
+            .owners
+            .iter()
+            .filter(|owner| check_witness(owner))
+            .count();
+        let did_signed = self
+            .did_owners
+            .iter()
+            .filter(|did| {
+                did_signers
+                    .iter()
+                    .any(|ds| ds.did == **did && check_witness(&ds.signer) && verify_did(ds))
+            })
+            .count();
+        (signed + did_signed) as u32
+    }
+
+    /// `addr` is one of the owners, or signs for one of the DID owners through `did_signers`
+    fn is_owned_by(&self, addr: &Address, did_signers: &[DidSigner]) -> bool {
+        self.owners.contains(addr)
+            || did_signers
+                .iter()
+                .any(|ds| &ds.signer == addr && self.did_owners.contains(&ds.did) && verify_did(ds))
+    }
+
+    fn check_witnesses(&self, did_signers: &[DidSigner]) {
+        assert!(
+            self.count_witnesses(did_signers) >= self.threshold.max(1),
+            "not enough owner signatures"
+        );
     }
 }
 
-/// register data id info on the block chain, need one of the owners signature
+/// register data id info on the block chain, need `threshold` of the owners signature
 ///
-/// `info_bytes` is the result of DataIdInfo struct
-pub fn register_data_id(info_bytes: &[u8]) -> bool {
+/// `info_bytes` is the result of DataIdInfo struct, the hashes are recorded as version 1
+///
+/// if the data is derived from other data ids, the owners of every parent must consent,
+/// see `register_derived_data_id` to prove a held dtoken instead
+///
+/// `did_signers` sign for the DID owners, it is empty when all the signing owners are addresses
+pub fn register_data_id(info_bytes: &[u8], did_signers: &[DidSigner]) -> bool {
+    register_derived_data_id(info_bytes, vec![], did_signers)
+}
+
+/// register data id info derived from other data ids, need `threshold` of the owners signature
+///
+/// for every parent, either `threshold` of the parent owners sign, or `proofs` has a proof
+/// that one of the owners holds a dtoken of the parent, the holder must sign as well
+pub fn register_derived_data_id(
+    info_bytes: &[u8],
+    proofs: Vec<ParentProof>,
+    did_signers: &[DidSigner],
+) -> bool {
     let mut source = Source::new(info_bytes);
     let data_id_info: DataIdInfo = source.read().unwrap();
-    assert!(data_id_info.owners.len() >= 1);
-    let mut valid = false;
-    for owner in data_id_info.owners.iter() {
-        if valid {
-            break;
+    check_new_data_id(&data_id_info);
+    data_id_info.check_witnesses(did_signers);
+    check_parents(&data_id_info, &proofs, did_signers, &mut BTreeSet::new());
+    let event = EventBuilder::new().string("registerDataId");
+    let event = match data_id_info.owners.first() {
+        Some(owner) => event.address(owner),
+        None => event.bytearray(&data_id_info.did_owners[0]),
+    };
+    event.bytearray(data_id_info.data_id.as_slice()).notify();
+    let mut additions = IndexAdditions::new();
+    save_data_id(data_id_info, &mut additions);
+    additions.write();
+    true
+}
+
+/// register many data ids at once, the whole batch fails if any of them fails
+///
+/// every DataIdInfo of the batch must have all the fields, the short layout of the data ids
+/// registered before the threshold was added is only accepted by `register_data_id`
+///
+/// the owner signatures are checked once for each distinct owner set in the batch,
+/// and the consent of a parent once however many data ids of the batch derive from it,
+/// a data id can be derived from the data ids before it in the batch
+///
+/// one `registerDataIds` event lists all the data ids instead of one event per data id,
+/// one `derivedFrom` event per parent lists its new children,
+/// and the index of a shared owner or parent is updated once for the whole batch
+pub fn register_data_ids(infos: Vec<DataIdInfo>, did_signers: &[DidSigner]) -> bool {
+    assert!(!infos.is_empty());
+    let mut data_ids: BTreeSet<&[u8]> = BTreeSet::new();
+    let mut checked: BTreeSet<(&[Address], &[Vec<u8>], u32)> = BTreeSet::new();
+    for info in infos.iter() {
+        assert!(
+            data_ids.insert(info.data_id.as_slice()),
+            "duplicate data id"
+        );
+        check_new_data_id(info);
+        let owner_set = (
+            info.owners.as_slice(),
+            info.did_owners.as_slice(),
+            info.threshold,
+        );
+        if checked.insert(owner_set) {
+            info.check_witnesses(did_signers);
         }
-        valid = check_witness(owner);
     }
-    assert!(valid);
-    database::put(
-        utils::generate_data_id_key(data_id_info.data_id.as_slice()),
-        info_bytes,
+    let mut event = EventBuilder::new()
+        .string("registerDataIds")
+        .number(infos.len() as U128);
+    let mut additions = IndexAdditions::new();
+    let mut consented = BTreeSet::new();
+    for info in infos {
+        check_parents(&info, &[], did_signers, &mut consented);
+        event = event.bytearray(&info.data_id);
+        save_data_id(info, &mut additions);
+    }
+    additions.write();
+    event.notify();
+    true
+}
+
+fn check_new_data_id(info: &DataIdInfo) {
+    check_owner_set(&info.owners, &info.did_owners, info.threshold);
+    assert!(
+        database::get::<_, DataIdInfo>(utils::generate_data_id_key(&info.data_id)).is_none(),
+        "data id already registered"
     );
+}
+
+/// record the data id with its hashes as version 1, its links to the parents and owners
+/// are added to `additions`
+fn save_data_id(mut info: DataIdInfo, additions: &mut IndexAdditions) {
+    info.version = 1;
+    add_version(&info);
+    for parent in info.parents.iter() {
+        additions.add_child(parent, &info.data_id);
+    }
+    for owner in info.owners.iter() {
+        additions.add_owner(utils::generate_owner_data_ids_key(owner), &info.data_id);
+    }
+    for did in info.did_owners.iter() {
+        additions.add_owner(utils::generate_did_data_ids_key(did), &info.data_id);
+    }
+    database::put(utils::generate_data_id_key(info.data_id.as_slice()), info);
+}
+
+/// index entries of newly registered data ids, grouped by index,
+/// so that the count of each index is read and written once for a whole batch
+/// and one `derivedFrom` event is emitted per parent
+struct IndexAdditions {
+    owners: BTreeMap<Vec<u8>, Vec<Vec<u8>>>, // owner index key to data ids
+    children: BTreeMap<Vec<u8>, Vec<Vec<u8>>>, // parent to data ids
+}
+
+impl IndexAdditions {
+    fn new() -> Self {
+        IndexAdditions {
+            owners: BTreeMap::new(),
+            children: BTreeMap::new(),
+        }
+    }
+
+    fn add_owner(&mut self, index_key: Vec<u8>, data_id: &[u8]) {
+        self.owners
+            .entry(index_key)
+            .or_insert_with(Vec::new)
+            .push(data_id.to_vec());
+    }
+
+    fn add_child(&mut self, parent: &[u8], data_id: &[u8]) {
+        self.children
+            .entry(parent.to_vec())
+            .or_insert_with(Vec::new)
+            .push(data_id.to_vec());
+    }
+
+    fn write(self) {
+        for (index_key, data_ids) in self.owners.iter() {
+            index_owner_data_ids(index_key, data_ids);
+        }
+        for (parent, data_ids) in self.children.iter() {
+            let mut count = get_child_count(parent);
+            let mut event = EventBuilder::new()
+                .string("derivedFrom")
+                .bytearray(parent)
+                .number(data_ids.len() as U128);
+            for data_id in data_ids.iter() {
+                database::put(utils::generate_children_key(parent, count), data_id);
+                count += 1;
+                event = event.bytearray(data_id);
+            }
+            database::put(utils::generate_child_count_key(parent), count);
+            event.notify();
+        }
+    }
+}
+
+/// publish a new version of the data, need `threshold` of the owners signature
+///
+/// the version number increases by 1, the former versions stay queryable
+pub fn publish_version(
+    data_id: &[u8],
+    data_hash: H256,
+    data_meta_hash: H256,
+    did_signers: &[DidSigner],
+) -> bool {
+    let mut info = get_registered(data_id);
+    info.check_witnesses(did_signers);
+    assert!(!is_revoked(data_id), "data id is revoked");
+    if !has_version(data_id, info.version) {
+        // registered before the versions were recorded, its hashes are recorded now
+        add_version(&info);
+    }
+    info.version = info.version.checked_add(1).unwrap();
+    info.data_hash = data_hash;
+    info.data_meta_hash = data_meta_hash;
+    add_version(&info);
     EventBuilder::new()
-        .string("registerDataId")
-        .address(&data_id_info.owners[0])
-        .bytearray(data_id_info.data_id.as_slice())
+        .string("publishVersion")
+        .bytearray(data_id)
+        .number(info.version as U128)
         .notify();
+    database::put(utils::generate_data_id_key(data_id), info);
     true
 }
 
-/// query data id info by data id
-pub fn get_data_id_info(id: Vec<u8>) -> DataIdInfo {
-    database::get::<_, DataIdInfo>(utils::generate_data_id_key(id.as_slice()))
-        .unwrap_or(DataIdInfo::default())
+/// retire the data id for good, need `threshold` of the owners signature
+///
+/// `reason_hash` is the sha256 of the reason, the reason itself is kept off chain
+pub fn revoke(data_id: &[u8], reason_hash: H256, did_signers: &[DidSigner]) -> bool {
+    let info = get_registered(data_id);
+    info.check_witnesses(did_signers);
+    assert!(get_revocation(data_id).is_none(), "data id already revoked");
+    let revocation = Revocation {
+        reason_hash,
+        revoked_at: timestamp(),
+    };
+    database::put(utils::generate_revoked_key(data_id), revocation);
+    unindex_owners(&info);
+    EventBuilder::new()
+        .string("revoke")
+        .bytearray(data_id)
+        .bytearray(reason_hash.as_ref())
+        .notify();
+    true
 }
 
-/// verify data id owner signature
-pub fn check_owner(data_id: Vec<u8>) -> bool {
-    let info = get_data_id_info(data_id);
-    let mut valid = false;
-    for owner in info.owners.iter() {
-        if valid {
-            break;
+/// query the revocation of the data id, None means it is not revoked by the owners
+pub fn get_revocation(data_id: &[u8]) -> Option<Revocation> {
+    database::get::<_, Revocation>(utils::generate_revoked_key(data_id))
+}
+
+/// freeze or unfreeze the data id, need admin signature
+pub fn set_frozen(data_id: &[u8], frozen: bool) -> bool {
+    assert!(check_witness(&get_admin()));
+    get_registered(data_id);
+    if frozen {
+        database::put(utils::generate_frozen_key(data_id), true);
+    } else {
+        database::delete(utils::generate_frozen_key(data_id));
+    }
+    EventBuilder::new()
+        .string(if frozen { "freeze" } else { "unfreeze" })
+        .bytearray(data_id)
+        .notify();
+    true
+}
+
+/// query whether the admin froze the data id
+pub fn is_frozen(data_id: &[u8]) -> bool {
+    database::get::<_, bool>(utils::generate_frozen_key(data_id)).unwrap_or(false)
+}
+
+/// the data id can not be used when it is revoked by the owners or frozen by the admin
+pub fn is_revoked(data_id: &[u8]) -> bool {
+    get_revocation(data_id).is_some() || is_frozen(data_id)
+}
+
+/// query the versions of the data id published after the `cursor`th one, the oldest first
+///
+/// at most `limit` versions are read, `limit` is capped at 100,
+/// so the next page starts from `cursor` plus the capped `limit`
+pub fn get_versions(data_id: &[u8], cursor: u32, limit: u32) -> Vec<DataVersion> {
+    let end = latest_version(data_id).min(cursor.saturating_add(limit.min(MAX_PAGE_SIZE)));
+    (cursor..end)
+        .filter_map(|i| get_version(data_id, Some(i + 1)))
+        .collect()
+}
+
+/// query the specified version of the data id, None means the latest version
+///
+/// it resolves the version a ddxf item pins for a TokenTemplate
+pub fn get_version(data_id: &[u8], version: Option<u32>) -> Option<DataVersion> {
+    let version = version.unwrap_or_else(|| latest_version(data_id));
+    database::get::<_, DataVersion>(utils::generate_data_version_key(data_id, version))
+}
+
+/// query whether the version of the data id has been published
+pub fn has_version(data_id: &[u8], version: u32) -> bool {
+    get_version(data_id, Some(version)).is_some()
+}
+
+/// the versions are numbered from 1, so the latest one is also the number of versions
+fn latest_version(data_id: &[u8]) -> u32 {
+    get_data_id_info(data_id.to_vec())
+        .map(|info| info.version)
+        .unwrap_or(0)
+}
+
+fn add_version(info: &DataIdInfo) {
+    let version = DataVersion {
+        version: info.version,
+        data_hash: info.data_hash.clone(),
+        data_meta_hash: info.data_meta_hash.clone(),
+        published_at: timestamp(),
+    };
+    database::put(
+        utils::generate_data_version_key(&info.data_id, info.version),
+        version,
+    );
+}
+
+/// `consented` holds the parents whose owners already signed, so that a parent shared by
+/// a batch is checked once, the consent proven by a held dtoken is not cached
+fn check_parents(
+    info: &DataIdInfo,
+    proofs: &[ParentProof],
+    did_signers: &[DidSigner],
+    consented: &mut BTreeSet<Vec<u8>>,
+) {
+    for (i, parent) in info.parents.iter().enumerate() {
+        assert!(!info.parents[..i].contains(parent), "duplicate parent");
+        if consented.contains(parent) {
+            continue;
+        }
+        let parent_info = get_registered(parent);
+        assert!(!is_revoked(parent), "parent data id is revoked");
+        if parent_info.count_witnesses(did_signers) >= parent_info.threshold.max(1) {
+            consented.insert(parent.clone());
+            continue;
+        }
+        let proof = proofs
+            .iter()
+            .find(|proof| &proof.parent == parent)
+            .expect("no consent of the parent owners");
+        assert!(
+            info.is_owned_by(&proof.holder, did_signers) && check_witness(&proof.holder),
+            "the holder is not an owner"
+        );
+        assert!(proof.token_template.data_id.as_ref() == Some(parent));
+        let dtoken = get_dtoken_contract().expect("dtoken contract not set");
+        let res = wasm::call_contract(
+            &dtoken,
+            (
+                "getCountAndAgent",
+                (
+                    proof.resource_id.as_slice(),
+                    &proof.holder,
+                    proof.token_template.to_bytes().as_slice(),
+                ),
+            ),
+        );
+        if let Some(r) = res {
+            let mut source = Source::new(r.as_slice());
+            let count: u32 = source.read().unwrap();
+            assert!(count > 0, "no dtoken of the parent");
+        } else {
+            panic!("call dtoken contract failed");
         }
-        valid = check_witness(owner);
     }
-    assert!(valid);
+}
+
+/// query the data ids the data id is directly derived from
+pub fn get_parents(data_id: &[u8]) -> Vec<Vec<u8>> {
+    get_registered(data_id).parents
+}
+
+/// query the number of data ids directly derived from the data id
+pub fn get_child_count(data_id: &[u8]) -> u32 {
+    database::get::<_, u32>(utils::generate_child_count_key(data_id)).unwrap_or(0)
+}
+
+/// query the data ids directly derived from the data id, in the order they were registered
+///
+/// at most `limit` data ids are returned starting from the `start`th one, `limit` is capped at 100
+pub fn get_children(data_id: &[u8], start: u32, limit: u32) -> Vec<Vec<u8>> {
+    read_children(data_id, start, limit.min(MAX_PAGE_SIZE))
+}
+
+fn read_children(data_id: &[u8], start: u32, limit: u32) -> Vec<Vec<u8>> {
+    let end = get_child_count(data_id).min(start.saturating_add(limit));
+    (start..end)
+        .filter_map(|i| database::get::<_, Vec<u8>>(utils::generate_children_key(data_id, i)))
+        .collect()
+}
+
+/// query the data ids the data id is derived from, directly or not, the nearest first
+///
+/// at most 100 data ids are returned, continue from the farthest ones to walk further
+pub fn get_ancestors(data_id: &[u8]) -> Vec<Vec<u8>> {
+    walk_lineage(data_id, |id, limit| {
+        get_parents(id).into_iter().take(limit as usize).collect()
+    })
+}
+
+/// query the data ids derived from the data id, directly or not, the nearest first
+///
+/// at most 100 data ids are returned, continue from the farthest ones to walk further
+pub fn get_descendants(data_id: &[u8]) -> Vec<Vec<u8>> {
+    walk_lineage(data_id, |id, limit| read_children(id, 0, limit))
+}
+
+/// breadth first walk, `next` returns at most `limit` of the links of a data id
+fn walk_lineage(data_id: &[u8], next: fn(&[u8], u32) -> Vec<Vec<u8>>) -> Vec<Vec<u8>> {
+    let mut seen: BTreeSet<Vec<u8>> = BTreeSet::new();
+    seen.insert(data_id.to_vec());
+    let mut found: Vec<Vec<u8>> = vec![];
+    let mut current = data_id.to_vec();
+    let mut i = 0;
+    loop {
+        // the links of one data id are distinct, so reading as many more as have been seen
+        // is enough to find every unseen one up to the cap
+        let remaining = MAX_LINEAGE_SIZE - found.len() as u32;
+        for id in next(&current, remaining + seen.len() as u32) {
+            if found.len() as u32 == MAX_LINEAGE_SIZE {
+                break;
+            }
+            if seen.insert(id.clone()) {
+                found.push(id);
+            }
+        }
+        if i == found.len() || found.len() as u32 == MAX_LINEAGE_SIZE {
+            return found;
+        }
+        current = found[i].clone();
+        i += 1;
+    }
+}
+
+/// need old admin signature
+///
+/// update the admin address, admin has the right to set the dtoken contract address
+fn update_admin(new_admin: &Address) -> bool {
+    let old_admin = get_admin();
+    assert!(check_witness(&old_admin));
+    database::put(KEY_ADMIN, new_admin);
+    true
+}
+
+/// query admin address
+fn get_admin() -> Address {
+    database::get::<_, Address>(KEY_ADMIN).unwrap_or(ADMIN)
+}
+
+/// set the dtoken contract which proves the derived data holds a dtoken of the parent, need admin signature
+pub fn set_dtoken_contract(dtoken: &Address) -> bool {
+    assert!(check_witness(&get_admin()));
+    database::put(KEY_DTOKEN_CONTRACT, dtoken);
+    true
+}
+
+/// query the dtoken contract address
+pub fn get_dtoken_contract() -> Option<Address> {
+    database::get::<_, Address>(KEY_DTOKEN_CONTRACT)
+}
+
+/// set the identity contract which verifies the signers of DID owners, need admin signature
+///
+/// the identity contract must support `verifyDid(did, signer, key_index) -> bool`
+pub fn set_identity_contract(identity: &Address) -> bool {
+    assert!(check_witness(&get_admin()));
+    database::put(KEY_IDENTITY_CONTRACT, identity);
+    true
+}
+
+/// query the identity contract address
+pub fn get_identity_contract() -> Option<Address> {
+    database::get::<_, Address>(KEY_IDENTITY_CONTRACT)
+}
+
+/// ask the identity contract whether the signer controls the DID with the key
+fn verify_did(did_signer: &DidSigner) -> bool {
+    let identity = get_identity_contract().expect("identity contract not set");
+    let res = wasm::call_contract(
+        &identity,
+        (
+            "verifyDid",
+            (
+                did_signer.did.as_slice(),
+                &did_signer.signer,
+                did_signer.key_index,
+            ),
+        ),
+    );
+    if let Some(r) = res {
+        let mut source = Source::new(r.as_slice());
+        source.read().unwrap()
+    } else {
+        panic!("call identity contract failed");
+    }
+}
+
+/// query data id info by data id, None means the data id is not registered
+pub fn get_data_id_info(id: Vec<u8>) -> Option<DataIdInfo> {
+    database::get::<_, DataIdInfo>(utils::generate_data_id_key(id.as_slice()))
+}
+
+/// query whether the data id is registered
+pub fn exists(data_id: &[u8]) -> bool {
+    get_data_id_info(data_id.to_vec()).is_some()
+}
+
+/// query whether `addr` is one of the owners of the data id, false if the data id is not registered
+///
+/// `addr` also owns the data id when it signs for one of the DID owners through `did_signers`,
+/// unlike `check_owner`, no signature is needed
+pub fn is_owner(data_id: &[u8], addr: &Address, did_signers: &[DidSigner]) -> bool {
+    get_data_id_info(data_id.to_vec())
+        .map(|info| info.is_owned_by(addr, did_signers))
+        .unwrap_or(false)
+}
+
+/// verify data id owner signature, need `threshold` of the owners signature
+pub fn check_owner(data_id: Vec<u8>, did_signers: &[DidSigner]) -> bool {
+    let info = get_registered(data_id.as_slice());
+    info.check_witnesses(did_signers);
     return true;
 }
 
+/// query whether `threshold` of the owners of the data id signed, false if the data id is not registered
+///
+/// unlike `check_owner` it does not fail, other contracts use it to check the owners consent
+pub fn is_signed_by_owners(data_id: &[u8], did_signers: &[DidSigner]) -> bool {
+    get_data_id_info(data_id.to_vec())
+        .map(|info| info.count_witnesses(did_signers) >= info.threshold.max(1))
+        .unwrap_or(false)
+}
+
+/// query the owners of the data id and the number of owner signatures needed
+pub fn get_owners(data_id: &[u8]) -> OwnerSet {
+    let info = get_registered(data_id);
+    OwnerSet {
+        threshold: info.threshold,
+        owners: info.owners,
+        did_owners: info.did_owners,
+    }
+}
+
+/// replace all the owners of the data id and the threshold, need `threshold` of the current owners signature
+pub fn transfer_ownership(
+    data_id: &[u8],
+    new_owners: Vec<Address>,
+    new_did_owners: Vec<Vec<u8>>,
+    threshold: u32,
+    did_signers: &[DidSigner],
+) -> bool {
+    let mut info = get_registered(data_id);
+    info.check_witnesses(did_signers);
+    assert!(!is_revoked(data_id), "data id is revoked");
+    check_owner_set(&new_owners, &new_did_owners, threshold);
+    let mut event = EventBuilder::new()
+        .string("transferOwnership")
+        .bytearray(data_id)
+        .number(threshold as U128);
+    for owner in new_owners.iter() {
+        event = event.address(owner);
+    }
+    for did in new_did_owners.iter() {
+        event = event.bytearray(did);
+    }
+    event.notify();
+    unindex_owners(&info);
+    info.owners = new_owners;
+    info.did_owners = new_did_owners;
+    info.threshold = threshold;
+    index_owners(&info);
+    database::put(utils::generate_data_id_key(data_id), info);
+    true
+}
+
+/// add an owner to the data id, need `threshold` of the current owners signature
+pub fn add_owner(data_id: &[u8], owner: &Address, did_signers: &[DidSigner]) -> bool {
+    let mut info = get_registered(data_id);
+    info.check_witnesses(did_signers);
+    assert!(!is_revoked(data_id), "data id is revoked");
+    assert!(!info.owners.contains(owner), "already an owner");
+    info.owners.push(owner.clone());
+    index_owner(utils::generate_owner_data_ids_key(owner), data_id);
+    database::put(utils::generate_data_id_key(data_id), info);
+    EventBuilder::new()
+        .string("addOwner")
+        .bytearray(data_id)
+        .address(owner)
+        .notify();
+    true
+}
+
+/// remove an owner from the data id, need `threshold` of the current owners signature
+///
+/// the owners left must be no fewer than the threshold, use `transfer_ownership` to change both
+pub fn remove_owner(data_id: &[u8], owner: &Address, did_signers: &[DidSigner]) -> bool {
+    let mut info = get_registered(data_id);
+    info.check_witnesses(did_signers);
+    assert!(!is_revoked(data_id), "data id is revoked");
+    assert!(info.owners.contains(owner), "not an owner");
+    assert!(
+        (info.owners.len() + info.did_owners.len()) as u32 > info.threshold,
+        "too few owners left"
+    );
+    info.owners.retain(|o| o != owner);
+    unindex_owner(utils::generate_owner_data_ids_key(owner), data_id);
+    database::put(utils::generate_data_id_key(data_id), info);
+    EventBuilder::new()
+        .string("removeOwner")
+        .bytearray(data_id)
+        .address(owner)
+        .notify();
+    true
+}
+
+/// add a DID owner to the data id, need `threshold` of the current owners signature
+pub fn add_did_owner(data_id: &[u8], did: &[u8], did_signers: &[DidSigner]) -> bool {
+    let mut info = get_registered(data_id);
+    info.check_witnesses(did_signers);
+    assert!(!is_revoked(data_id), "data id is revoked");
+    assert!(
+        !info.did_owners.iter().any(|d| d.as_slice() == did),
+        "already an owner"
+    );
+    info.did_owners.push(did.to_vec());
+    index_owner(utils::generate_did_data_ids_key(did), data_id);
+    database::put(utils::generate_data_id_key(data_id), info);
+    EventBuilder::new()
+        .string("addDidOwner")
+        .bytearray(data_id)
+        .bytearray(did)
+        .notify();
+    true
+}
+
+/// remove a DID owner from the data id, need `threshold` of the current owners signature
+pub fn remove_did_owner(data_id: &[u8], did: &[u8], did_signers: &[DidSigner]) -> bool {
+    let mut info = get_registered(data_id);
+    info.check_witnesses(did_signers);
+    assert!(!is_revoked(data_id), "data id is revoked");
+    assert!(
+        info.did_owners.iter().any(|d| d.as_slice() == did),
+        "not an owner"
+    );
+    assert!(
+        (info.owners.len() + info.did_owners.len()) as u32 > info.threshold,
+        "too few owners left"
+    );
+    info.did_owners.retain(|d| d.as_slice() != did);
+    unindex_owner(utils::generate_did_data_ids_key(did), data_id);
+    database::put(utils::generate_data_id_key(data_id), info);
+    EventBuilder::new()
+        .string("removeDidOwner")
+        .bytearray(data_id)
+        .bytearray(did)
+        .notify();
+    true
+}
+
+/// query the data ids owned by `owner` which are not revoked, in the order they were owned
+///
+/// at most `limit` index entries are read starting from the `cursor`th one, `limit` is capped at 100,
+/// the entries of the data ids the owner no longer has are skipped,
+/// so the next page starts from `cursor` plus the capped `limit`
+pub fn get_data_ids_by_owner(owner: &Address, cursor: u32, limit: u32) -> Vec<Vec<u8>> {
+    page(&utils::generate_owner_data_ids_key(owner), cursor, limit)
+}
+
+/// query the data ids owned by the DID which are not revoked, paged like `get_data_ids_by_owner`
+pub fn get_data_ids_by_did(did: &[u8], cursor: u32, limit: u32) -> Vec<Vec<u8>> {
+    page(&utils::generate_did_data_ids_key(did), cursor, limit)
+}
+
+/// query the number of entries in the index of `owner`, the skipped ones included
+pub fn get_owner_index_count(owner: &Address) -> u32 {
+    index_count(&utils::generate_owner_data_ids_key(owner))
+}
+
+/// query the number of entries in the index of the DID, the skipped ones included
+pub fn get_did_index_count(did: &[u8]) -> u32 {
+    index_count(&utils::generate_did_data_ids_key(did))
+}
+
+fn page(index_key: &[u8], cursor: u32, limit: u32) -> Vec<Vec<u8>> {
+    let end = index_count(index_key).min(cursor.saturating_add(limit.min(MAX_PAGE_SIZE)));
+    (cursor..end)
+        .filter_map(|i| database::get::<_, Vec<u8>>(utils::generate_index_entry_key(index_key, i)))
+        .collect()
+}
+
+fn index_count(index_key: &[u8]) -> u32 {
+    database::get::<_, u32>(utils::generate_index_count_key(index_key)).unwrap_or(0)
+}
+
+fn index_owners(info: &DataIdInfo) {
+    for owner in info.owners.iter() {
+        index_owner(utils::generate_owner_data_ids_key(owner), &info.data_id);
+    }
+    for did in info.did_owners.iter() {
+        index_owner(utils::generate_did_data_ids_key(did), &info.data_id);
+    }
+}
+
+fn unindex_owners(info: &DataIdInfo) {
+    for owner in info.owners.iter() {
+        unindex_owner(utils::generate_owner_data_ids_key(owner), &info.data_id);
+    }
+    for did in info.did_owners.iter() {
+        unindex_owner(utils::generate_did_data_ids_key(did), &info.data_id);
+    }
+}
+
+fn index_owner(index_key: Vec<u8>, data_id: &[u8]) {
+    index_owner_data_ids(&index_key, &[data_id.to_vec()]);
+}
+
+fn index_owner_data_ids(index_key: &[u8], data_ids: &[Vec<u8>]) {
+    let mut count = index_count(index_key);
+    for data_id in data_ids.iter() {
+        database::put(utils::generate_index_entry_key(index_key, count), data_id);
+        database::put(
+            utils::generate_index_position_key(index_key, data_id),
+            count,
+        );
+        count += 1;
+    }
+    database::put(utils::generate_index_count_key(index_key), count);
+}
+
+/// the entry is left empty, so that the cursors after it do not move
+fn unindex_owner(index_key: Vec<u8>, data_id: &[u8]) {
+    let position_key = utils::generate_index_position_key(&index_key, data_id);
+    if let Some(position) = database::get::<_, u32>(&position_key) {
+        database::delete(utils::generate_index_entry_key(&index_key, position));
+        database::delete(position_key);
+    }
+}
+
+/// change the number of owner signatures needed, need `threshold` of the current owners signature
+pub fn set_threshold(data_id: &[u8], threshold: u32, did_signers: &[DidSigner]) -> bool {
+    let mut info = get_registered(data_id);
+    info.check_witnesses(did_signers);
+    assert!(!is_revoked(data_id), "data id is revoked");
+    check_owner_set(&info.owners, &info.did_owners, threshold);
+    info.threshold = threshold;
+    database::put(utils::generate_data_id_key(data_id), info);
+    EventBuilder::new()
+        .string("setThreshold")
+        .bytearray(data_id)
+        .number(threshold as U128)
+        .notify();
+    true
+}
+
+fn get_registered(data_id: &[u8]) -> DataIdInfo {
+    database::get::<_, DataIdInfo>(utils::generate_data_id_key(data_id))
+        .expect("data id not registered")
+}
+
+fn check_owner_set(owners: &[Address], did_owners: &[Vec<u8>], threshold: u32) {
+    let total = owners.len() + did_owners.len();
+    assert!(total >= 1);
+    for (i, owner) in owners.iter().enumerate() {
+        assert!(!owners[..i].contains(owner), "duplicate owner");
+    }
+    for (i, did) in did_owners.iter().enumerate() {
+        assert!(!did_owners[..i].contains(did), "duplicate owner");
+    }
+    assert!(threshold >= 1 && threshold as usize <= total);
+}
+
 #[no_mangle]
 pub fn invoke() {
     let input = input();
@@ -88,15 +916,186 @@ pub fn invoke() {
     match action {
         b"registerDataId" => {
             let data_id_bytes: &[u8] = source.read().unwrap();
-            sink.write(register_data_id(data_id_bytes));
+            let did_signers = read_did_signers(&mut source);
+            sink.write(register_data_id(data_id_bytes, &did_signers));
+        }
+        b"registerDataIds" => {
+            let infos: Vec<DataIdInfo> = source.read().unwrap();
+            let did_signers = read_did_signers(&mut source);
+            sink.write(register_data_ids(infos, &did_signers));
+        }
+        b"registerDerivedDataId" => {
+            let (info_bytes, proofs): (&[u8], Vec<ParentProof>) = source.read().unwrap();
+            let did_signers = read_did_signers(&mut source);
+            sink.write(register_derived_data_id(info_bytes, proofs, &did_signers));
+        }
+        b"getParents" => {
+            let data_id: &[u8] = source.read().unwrap();
+            sink.write(get_parents(data_id));
+        }
+        b"getChildren" => {
+            let (data_id, start, limit): (&[u8], u32, u32) = source.read().unwrap();
+            sink.write(get_children(data_id, start, limit));
+        }
+        b"getChildCount" => {
+            let data_id: &[u8] = source.read().unwrap();
+            sink.write(get_child_count(data_id));
+        }
+        b"getAncestors" => {
+            let data_id: &[u8] = source.read().unwrap();
+            sink.write(get_ancestors(data_id));
+        }
+        b"getDescendants" => {
+            let data_id: &[u8] = source.read().unwrap();
+            sink.write(get_descendants(data_id));
+        }
+        b"updateAdmin" => {
+            let new_admin = source.read().unwrap();
+            sink.write(update_admin(&new_admin));
+        }
+        b"getAdmin" => {
+            sink.write(get_admin());
+        }
+        b"setDtokenContract" => {
+            let dtoken = source.read().unwrap();
+            sink.write(set_dtoken_contract(&dtoken));
+        }
+        b"getDtokenContract" => {
+            sink.write(get_dtoken_contract());
+        }
+        b"setIdentityContract" => {
+            let identity = source.read().unwrap();
+            sink.write(set_identity_contract(&identity));
+        }
+        b"getIdentityContract" => {
+            sink.write(get_identity_contract());
         }
         b"get_data_id_info" => {
             let data_id: Vec<u8> = source.read().unwrap();
             sink.write(get_data_id_info(data_id));
         }
+        b"exists" => {
+            let data_id: &[u8] = source.read().unwrap();
+            sink.write(exists(data_id));
+        }
+        b"isOwner" => {
+            let (data_id, addr): (&[u8], Address) = source.read().unwrap();
+            let did_signers = read_did_signers(&mut source);
+            sink.write(is_owner(data_id, &addr, &did_signers));
+        }
         b"check_owner" => {
             let data_id = source.read().unwrap();
-            sink.write(check_owner(data_id));
+            let did_signers = read_did_signers(&mut source);
+            sink.write(check_owner(data_id, &did_signers));
+        }
+        b"isSignedByOwners" => {
+            let data_id: &[u8] = source.read().unwrap();
+            let did_signers = read_did_signers(&mut source);
+            sink.write(is_signed_by_owners(data_id, &did_signers));
+        }
+        b"publishVersion" => {
+            let (data_id, data_hash, data_meta_hash): (&[u8], H256, H256) = source.read().unwrap();
+            let did_signers = read_did_signers(&mut source);
+            sink.write(publish_version(
+                data_id,
+                data_hash,
+                data_meta_hash,
+                &did_signers,
+            ));
+        }
+        b"getVersions" => {
+            let (data_id, cursor, limit) = source.read().unwrap();
+            sink.write(get_versions(data_id, cursor, limit));
+        }
+        b"getVersion" => {
+            let (data_id, version): (&[u8], Option<u32>) = source.read().unwrap();
+            sink.write(get_version(data_id, version));
+        }
+        b"hasVersion" => {
+            let (data_id, version) = source.read().unwrap();
+            sink.write(has_version(data_id, version));
+        }
+        b"revoke" => {
+            let (data_id, reason_hash): (&[u8], H256) = source.read().unwrap();
+            let did_signers = read_did_signers(&mut source);
+            sink.write(revoke(data_id, reason_hash, &did_signers));
+        }
+        b"getRevocation" => {
+            let data_id: &[u8] = source.read().unwrap();
+            sink.write(get_revocation(data_id));
+        }
+        b"setFrozen" => {
+            let (data_id, frozen): (&[u8], bool) = source.read().unwrap();
+            sink.write(set_frozen(data_id, frozen));
+        }
+        b"isFrozen" => {
+            let data_id: &[u8] = source.read().unwrap();
+            sink.write(is_frozen(data_id));
+        }
+        b"isRevoked" => {
+            let data_id: &[u8] = source.read().unwrap();
+            sink.write(is_revoked(data_id));
+        }
+        b"getOwners" => {
+            let data_id: &[u8] = source.read().unwrap();
+            sink.write(get_owners(data_id));
+        }
+        b"getDataIdsByOwner" => {
+            let (owner, cursor, limit): (Address, u32, u32) = source.read().unwrap();
+            sink.write(get_data_ids_by_owner(&owner, cursor, limit));
+        }
+        b"getDataIdsByDid" => {
+            let (did, cursor, limit): (&[u8], u32, u32) = source.read().unwrap();
+            sink.write(get_data_ids_by_did(did, cursor, limit));
+        }
+        b"getOwnerIndexCount" => {
+            let owner: Address = source.read().unwrap();
+            sink.write(get_owner_index_count(&owner));
+        }
+        b"getDidIndexCount" => {
+            let did: &[u8] = source.read().unwrap();
+            sink.write(get_did_index_count(did));
+        }
+        b"transferOwnership" => {
+            let (data_id, new_owners, new_did_owners, threshold): (
+                &[u8],
+                Vec<Address>,
+                Vec<Vec<u8>>,
+                u32,
+            ) = source.read().unwrap();
+            let did_signers = read_did_signers(&mut source);
+            sink.write(transfer_ownership(
+                data_id,
+                new_owners,
+                new_did_owners,
+                threshold,
+                &did_signers,
+            ));
+        }
+        b"setThreshold" => {
+            let (data_id, threshold): (&[u8], u32) = source.read().unwrap();
+            let did_signers = read_did_signers(&mut source);
+            sink.write(set_threshold(data_id, threshold, &did_signers));
+        }
+        b"addOwner" => {
+            let (data_id, owner): (&[u8], Address) = source.read().unwrap();
+            let did_signers = read_did_signers(&mut source);
+            sink.write(add_owner(data_id, &owner, &did_signers));
+        }
+        b"removeOwner" => {
+            let (data_id, owner): (&[u8], Address) = source.read().unwrap();
+            let did_signers = read_did_signers(&mut source);
+            sink.write(remove_owner(data_id, &owner, &did_signers));
+        }
+        b"addDidOwner" => {
+            let (data_id, did): (&[u8], &[u8]) = source.read().unwrap();
+            let did_signers = read_did_signers(&mut source);
+            sink.write(add_did_owner(data_id, did, &did_signers));
+        }
+        b"removeDidOwner" => {
+            let (data_id, did): (&[u8], &[u8]) = source.read().unwrap();
+            let did_signers = read_did_signers(&mut source);
+            sink.write(remove_did_owner(data_id, did, &did_signers));
         }
         _ => {
             let method = str::from_utf8(action).ok().unwrap();
@@ -111,6 +1110,53 @@ mod utils {
     pub fn generate_data_id_key(data_id: &[u8]) -> Vec<u8> {
         [KEY_DATA_ID, data_id].concat()
     }
+
+    pub fn generate_data_version_key(data_id: &[u8], version: u32) -> Vec<u8> {
+        [KEY_DATA_VERSIONS, data_id, version.to_le_bytes().as_ref()].concat()
+    }
+
+    pub fn generate_children_key(data_id: &[u8], index: u32) -> Vec<u8> {
+        [KEY_CHILDREN, data_id, index.to_le_bytes().as_ref()].concat()
+    }
+
+    pub fn generate_child_count_key(data_id: &[u8]) -> Vec<u8> {
+        [KEY_CHILD_COUNT, data_id].concat()
+    }
+
+    pub fn generate_revoked_key(data_id: &[u8]) -> Vec<u8> {
+        [KEY_REVOKED, data_id].concat()
+    }
+
+    pub fn generate_frozen_key(data_id: &[u8]) -> Vec<u8> {
+        [KEY_FROZEN, data_id].concat()
+    }
+
+    pub fn generate_owner_data_ids_key(owner: &Address) -> Vec<u8> {
+        [KEY_OWNER_DATA_IDS, owner.as_ref()].concat()
+    }
+
+    pub fn generate_did_data_ids_key(did: &[u8]) -> Vec<u8> {
+        [KEY_DID_DATA_IDS, did].concat()
+    }
+
+    pub fn generate_index_entry_key(index_key: &[u8], index: u32) -> Vec<u8> {
+        [index_key, index.to_le_bytes().as_ref()].concat()
+    }
+
+    pub fn generate_index_count_key(index_key: &[u8]) -> Vec<u8> {
+        [KEY_OWNER_INDEX_COUNT, index_key].concat()
+    }
+
+    pub fn generate_index_position_key(index_key: &[u8], data_id: &[u8]) -> Vec<u8> {
+        [
+            KEY_OWNER_INDEX_POSITION,
+            (index_key.len() as u32).to_le_bytes().as_ref(),
+            index_key,
+            (data_id.len() as u32).to_le_bytes().as_ref(),
+            data_id,
+        ]
+        .concat()
+    }
 }
 
 #[cfg(test)]
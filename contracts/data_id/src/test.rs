@@ -0,0 +1,737 @@
+use super::ostd::mock::build_runtime;
+use super::*;
+use core::sync::atomic::{AtomicU32, Ordering};
+
+fn info_bytes(data_id: &[u8], owners: Vec<Address>, threshold: u32) -> Vec<u8> {
+    derived_bytes(data_id, owners, threshold, vec![])
+}
+
+fn derived_bytes(
+    data_id: &[u8],
+    owners: Vec<Address>,
+    threshold: u32,
+    parents: Vec<Vec<u8>>,
+) -> Vec<u8> {
+    let info = DataIdInfo {
+        data_id: data_id.to_vec(),
+        data_type: RT::Other,
+        data_meta_hash: H256::repeat_byte(1),
+        data_hash: H256::repeat_byte(2),
+        owners,
+        did_owners: vec![],
+        threshold,
+        version: 0,
+        parents,
+    };
+    let mut sink = Sink::new(64);
+    sink.write(info);
+    sink.bytes().to_vec()
+}
+
+#[test]
+fn register_and_transfer_ownership() {
+    let handle = build_runtime();
+    let alice = Address::repeat_byte(1);
+    let bob = Address::repeat_byte(2);
+    let data_id = b"did:ont:data1";
+
+    handle.witness(&[alice.clone()]);
+    assert!(register_data_id(
+        &info_bytes(data_id, vec![alice.clone()], 1),
+        &[]
+    ));
+    assert!(add_owner(data_id, &bob, &[]));
+    assert_eq!(
+        get_data_id_info(data_id.to_vec()).unwrap().owners,
+        vec![alice, bob]
+    );
+    assert!(remove_owner(data_id, &alice, &[]));
+
+    handle.witness(&[bob.clone()]);
+    let carol = Address::repeat_byte(3);
+    assert!(transfer_ownership(
+        data_id,
+        vec![carol.clone()],
+        vec![],
+        1,
+        &[]
+    ));
+    assert_eq!(
+        get_data_id_info(data_id.to_vec()).unwrap().owners,
+        vec![carol]
+    );
+}
+
+#[test]
+#[should_panic(expected = "data id already registered")]
+fn register_twice() {
+    let handle = build_runtime();
+    let alice = Address::repeat_byte(1);
+    let mallory = Address::repeat_byte(9);
+    let data_id = b"did:ont:data1";
+    handle.witness(&[alice.clone()]);
+    assert!(register_data_id(
+        &info_bytes(data_id, vec![alice.clone()], 1),
+        &[]
+    ));
+    handle.witness(&[mallory.clone()]);
+    register_data_id(&info_bytes(data_id, vec![mallory.clone()], 1), &[]);
+}
+
+#[test]
+#[should_panic]
+fn add_owner_by_outsider() {
+    let handle = build_runtime();
+    let alice = Address::repeat_byte(1);
+    let mallory = Address::repeat_byte(9);
+    let data_id = b"did:ont:data1";
+    handle.witness(&[alice.clone()]);
+    assert!(register_data_id(
+        &info_bytes(data_id, vec![alice.clone()], 1),
+        &[]
+    ));
+    handle.witness(&[mallory.clone()]);
+    add_owner(data_id, &mallory, &[]);
+}
+
+#[test]
+fn threshold_owners() {
+    let handle = build_runtime();
+    let owners = vec![
+        Address::repeat_byte(1),
+        Address::repeat_byte(2),
+        Address::repeat_byte(3),
+    ];
+    let data_id = b"did:ont:joint";
+    handle.witness(&owners[..2]);
+    assert!(register_data_id(
+        &info_bytes(data_id, owners.clone(), 2),
+        &[]
+    ));
+    assert!(check_owner(data_id.to_vec(), &[]));
+    assert!(is_signed_by_owners(data_id, &[]));
+    handle.witness(&owners[..1]);
+    assert!(!is_signed_by_owners(data_id, &[]));
+    handle.witness(&owners[..2]);
+    let set = get_owners(data_id);
+    assert_eq!(set.threshold, 2);
+    assert_eq!(set.owners, owners);
+
+    assert!(remove_owner(data_id, &owners[2], &[]));
+    assert!(set_threshold(data_id, 1, &[]));
+    handle.witness(&owners[1..2]);
+    assert!(check_owner(data_id.to_vec(), &[]));
+}
+
+#[test]
+#[should_panic(expected = "not enough owner signatures")]
+fn threshold_not_reached() {
+    let handle = build_runtime();
+    let owners = vec![Address::repeat_byte(1), Address::repeat_byte(2)];
+    let data_id = b"did:ont:joint";
+    handle.witness(&owners[..]);
+    assert!(register_data_id(
+        &info_bytes(data_id, owners.clone(), 2),
+        &[]
+    ));
+    handle.witness(&owners[..1]);
+    add_owner(data_id, &Address::repeat_byte(3), &[]);
+}
+
+#[test]
+fn publish_versions() {
+    let handle = build_runtime();
+    let alice = Address::repeat_byte(1);
+    let data_id = b"did:ont:data1";
+    handle.witness(&[alice.clone()]);
+    handle.timestamp(10);
+    assert!(register_data_id(
+        &info_bytes(data_id, vec![alice.clone()], 1),
+        &[]
+    ));
+    assert_eq!(get_data_id_info(data_id.to_vec()).unwrap().version, 1);
+
+    handle.timestamp(20);
+    assert!(publish_version(
+        data_id,
+        H256::repeat_byte(3),
+        H256::repeat_byte(4),
+        &[]
+    ));
+    let info = get_data_id_info(data_id.to_vec()).unwrap();
+    assert_eq!(info.version, 2);
+    assert_eq!(info.data_hash, H256::repeat_byte(3));
+
+    let versions = get_versions(data_id, 0, 10);
+    assert_eq!(versions.len(), 2);
+    assert_eq!(versions[0].data_hash, H256::repeat_byte(2));
+    assert_eq!(versions[1].published_at, 20);
+    let versions = get_versions(data_id, 1, 10);
+    assert_eq!(versions.len(), 1);
+    assert_eq!(versions[0].version, 2);
+    assert_eq!(get_versions(data_id, 0, 1).len(), 1);
+    assert_eq!(get_version(data_id, Some(1)).unwrap().version, 1);
+    assert_eq!(get_version(data_id, None).unwrap().version, 2);
+    assert!(get_version(data_id, Some(3)).is_none());
+    assert!(has_version(data_id, 2));
+    assert!(!has_version(data_id, 3));
+}
+
+#[test]
+fn derived_with_parent_owner_consent() {
+    let handle = build_runtime();
+    let alice = Address::repeat_byte(1);
+    let bob = Address::repeat_byte(2);
+    handle.witness(&[alice.clone()]);
+    assert!(register_data_id(
+        &info_bytes(b"raw1", vec![alice.clone()], 1),
+        &[]
+    ));
+    assert!(register_data_id(
+        &info_bytes(b"raw2", vec![alice.clone()], 1),
+        &[]
+    ));
+
+    handle.witness(&[alice.clone(), bob.clone()]);
+    let parents = vec![b"raw1".to_vec(), b"raw2".to_vec()];
+    assert!(register_data_id(
+        &derived_bytes(b"clean", vec![bob.clone()], 1, parents.clone()),
+        &[]
+    ));
+    handle.witness(&[bob.clone()]);
+    assert!(register_data_id(
+        &derived_bytes(b"model", vec![bob.clone()], 1, vec![b"clean".to_vec()]),
+        &[]
+    ));
+
+    assert_eq!(get_parents(b"clean"), parents);
+    assert_eq!(get_children(b"raw1", 0, 10), vec![b"clean".to_vec()]);
+    assert_eq!(
+        get_ancestors(b"model"),
+        vec![b"clean".to_vec(), b"raw1".to_vec(), b"raw2".to_vec()]
+    );
+    assert_eq!(
+        get_descendants(b"raw2"),
+        vec![b"clean".to_vec(), b"model".to_vec()]
+    );
+}
+
+#[test]
+fn lineage_is_capped() {
+    let handle = build_runtime();
+    let alice = Address::repeat_byte(1);
+    handle.witness(&[alice.clone()]);
+    assert!(register_data_id(
+        &info_bytes(b"raw", vec![alice.clone()], 1),
+        &[]
+    ));
+    let infos = (0..120u32)
+        .map(|i| {
+            let data_id = [b"clean".as_ref(), i.to_le_bytes().as_ref()].concat();
+            info(&data_id, vec![alice.clone()], vec![b"raw".to_vec()])
+        })
+        .collect();
+    assert!(register_data_ids(infos, &[]));
+
+    assert_eq!(get_child_count(b"raw"), 120);
+    assert_eq!(get_children(b"raw", 0, 200).len(), 100);
+    assert_eq!(
+        get_children(b"raw", 110, 10)[0],
+        [b"clean".as_ref(), 110u32.to_le_bytes().as_ref()].concat()
+    );
+    let descendants = get_descendants(b"raw");
+    assert_eq!(descendants.len(), 100);
+    assert_eq!(descendants[99], get_children(b"raw", 99, 1)[0]);
+}
+
+#[test]
+fn data_id_in_legacy_layout() {
+    let handle = build_runtime();
+    let alice = Address::repeat_byte(1);
+    let mut sink = Sink::new(64);
+    sink.write(b"data1".to_vec());
+    sink.write(RT::Other);
+    sink.write(H256::repeat_byte(1));
+    sink.write(H256::repeat_byte(2));
+    sink.write(vec![alice.clone()]);
+    database::put(utils::generate_data_id_key(b"data1"), sink.bytes());
+
+    let info = get_data_id_info(b"data1".to_vec()).unwrap();
+    assert_eq!(info.owners, vec![alice.clone()]);
+    assert_eq!(info.threshold, 1);
+    assert_eq!(info.version, 1);
+    assert!(info.parents.is_empty() && info.did_owners.is_empty());
+
+    handle.witness(&[alice.clone()]);
+    assert!(check_owner(b"data1".to_vec(), &[]));
+    assert!(publish_version(
+        b"data1",
+        H256::repeat_byte(3),
+        H256::repeat_byte(4),
+        &[]
+    ));
+    let versions = get_versions(b"data1", 0, 10);
+    assert_eq!(versions.len(), 2);
+    assert_eq!(versions[0].data_hash, H256::repeat_byte(2));
+    assert_eq!(versions[1].version, 2);
+}
+
+#[test]
+#[should_panic(expected = "no consent of the parent owners")]
+fn derived_without_consent() {
+    let handle = build_runtime();
+    let alice = Address::repeat_byte(1);
+    let bob = Address::repeat_byte(2);
+    handle.witness(&[alice.clone()]);
+    assert!(register_data_id(
+        &info_bytes(b"raw1", vec![alice.clone()], 1),
+        &[]
+    ));
+    handle.witness(&[bob.clone()]);
+    register_data_id(
+        &derived_bytes(b"clean", vec![bob.clone()], 1, vec![b"raw1".to_vec()]),
+        &[],
+    );
+}
+
+#[test]
+fn derived_with_dtoken_proof() {
+    let handle = build_runtime();
+    let alice = Address::repeat_byte(1);
+    let bob = Address::repeat_byte(2);
+    handle.witness(&[alice.clone()]);
+    assert!(register_data_id(
+        &info_bytes(b"raw1", vec![alice.clone()], 1),
+        &[]
+    ));
+
+    handle.witness(&[ADMIN.clone()]);
+    assert!(set_dtoken_contract(&Address::repeat_byte(7)));
+    handle.on_contract_call(|_, _| {
+        let mut sink = Sink::new(16);
+        sink.write(1u32);
+        sink.write(Vec::<Address>::new());
+        Some(sink.bytes().to_vec())
+    });
+
+    handle.witness(&[bob.clone()]);
+    let proof = ParentProof {
+        parent: b"raw1".to_vec(),
+        resource_id: b"resource1".to_vec(),
+        token_template: TokenTemplate::new(Some(b"raw1".to_vec()), vec![]),
+        holder: bob.clone(),
+    };
+    assert!(register_derived_data_id(
+        &derived_bytes(b"clean", vec![bob.clone()], 1, vec![b"raw1".to_vec()]),
+        vec![proof],
+        &[]
+    ));
+    assert_eq!(get_descendants(b"raw1"), vec![b"clean".to_vec()]);
+}
+
+#[test]
+fn revoke_and_freeze() {
+    let handle = build_runtime();
+    let alice = Address::repeat_byte(1);
+    handle.witness(&[alice.clone()]);
+    assert!(register_data_id(
+        &info_bytes(b"data1", vec![alice.clone()], 1),
+        &[]
+    ));
+    assert!(register_data_id(
+        &info_bytes(b"data2", vec![alice.clone()], 1),
+        &[]
+    ));
+    assert!(!is_revoked(b"data1"));
+
+    assert!(revoke(b"data1", H256::repeat_byte(3), &[]));
+    assert!(is_revoked(b"data1"));
+    assert!(get_revocation(b"data1").is_some());
+
+    handle.witness(&[ADMIN.clone()]);
+    assert!(set_frozen(b"data2", true));
+    assert!(is_revoked(b"data2"));
+    assert!(get_revocation(b"data2").is_none());
+    assert!(set_frozen(b"data2", false));
+    assert!(!is_revoked(b"data2"));
+}
+
+#[test]
+#[should_panic(expected = "data id is revoked")]
+fn publish_version_after_revoke() {
+    let handle = build_runtime();
+    let alice = Address::repeat_byte(1);
+    handle.witness(&[alice.clone()]);
+    assert!(register_data_id(
+        &info_bytes(b"data1", vec![alice.clone()], 1),
+        &[]
+    ));
+    assert!(revoke(b"data1", H256::repeat_byte(3), &[]));
+    publish_version(b"data1", H256::repeat_byte(4), H256::repeat_byte(5), &[]);
+}
+
+#[test]
+#[should_panic(expected = "data id is revoked")]
+fn transfer_ownership_after_revoke() {
+    let handle = build_runtime();
+    let alice = Address::repeat_byte(1);
+    handle.witness(&[alice.clone()]);
+    assert!(register_data_id(
+        &info_bytes(b"data1", vec![alice.clone()], 1),
+        &[]
+    ));
+    assert!(revoke(b"data1", H256::repeat_byte(3), &[]));
+    transfer_ownership(b"data1", vec![Address::repeat_byte(2)], vec![], 1, &[]);
+}
+
+#[test]
+#[should_panic(expected = "data id is revoked")]
+fn add_owner_when_frozen() {
+    let handle = build_runtime();
+    let alice = Address::repeat_byte(1);
+    handle.witness(&[alice.clone(), ADMIN.clone()]);
+    assert!(register_data_id(
+        &info_bytes(b"data1", vec![alice.clone()], 1),
+        &[]
+    ));
+    assert!(set_frozen(b"data1", true));
+    add_owner(b"data1", &Address::repeat_byte(2), &[]);
+}
+
+#[test]
+fn query_unknown_data_id() {
+    let handle = build_runtime();
+    let alice = Address::repeat_byte(1);
+    let bob = Address::repeat_byte(2);
+    assert!(!exists(b"data1"));
+    assert!(get_data_id_info(b"data1".to_vec()).is_none());
+    assert!(!is_owner(b"data1", &alice, &[]));
+
+    handle.witness(&[alice.clone()]);
+    assert!(register_data_id(
+        &info_bytes(b"data1", vec![alice.clone()], 1),
+        &[]
+    ));
+    assert!(exists(b"data1"));
+    assert!(is_owner(b"data1", &alice, &[]));
+    assert!(!is_owner(b"data1", &bob, &[]));
+}
+
+#[test]
+#[should_panic(expected = "data id not registered")]
+fn check_owner_of_unknown_data_id() {
+    let handle = build_runtime();
+    handle.witness(&[Address::repeat_byte(1)]);
+    check_owner(b"data1".to_vec(), &[]);
+}
+
+#[test]
+fn data_ids_by_owner() {
+    let handle = build_runtime();
+    let alice = Address::repeat_byte(1);
+    let bob = Address::repeat_byte(2);
+    handle.witness(&[alice.clone()]);
+    for id in [b"data1", b"data2", b"data3"].iter() {
+        assert!(register_data_id(
+            &info_bytes(*id, vec![alice.clone()], 1),
+            &[]
+        ));
+    }
+    assert_eq!(
+        get_data_ids_by_owner(&alice, 0, 2),
+        vec![b"data1".to_vec(), b"data2".to_vec()]
+    );
+    assert_eq!(get_data_ids_by_owner(&alice, 2, 2), vec![b"data3".to_vec()]);
+
+    assert!(transfer_ownership(
+        b"data1",
+        vec![bob.clone()],
+        vec![],
+        1,
+        &[]
+    ));
+    assert!(add_owner(b"data2", &bob, &[]));
+    assert!(revoke(b"data3", H256::repeat_byte(3), &[]));
+    assert_eq!(
+        get_data_ids_by_owner(&alice, 0, 10),
+        vec![b"data2".to_vec()]
+    );
+    assert_eq!(
+        get_data_ids_by_owner(&bob, 0, 10),
+        vec![b"data1".to_vec(), b"data2".to_vec()]
+    );
+    // the entries of data1 and data3 are left empty, the cursors do not move
+    assert_eq!(get_owner_index_count(&alice), 3);
+    assert_eq!(get_data_ids_by_owner(&alice, 1, 1), vec![b"data2".to_vec()]);
+    assert!(get_data_ids_by_owner(&alice, 2, 1).is_empty());
+}
+
+#[test]
+fn index_position_keys_do_not_overlap() {
+    assert_ne!(
+        utils::generate_index_position_key(b"did:ont:a", b"bc"),
+        utils::generate_index_position_key(b"did:ont:ab", b"c")
+    );
+}
+
+fn did_info_bytes(data_id: &[u8], did_owners: Vec<Vec<u8>>, threshold: u32) -> Vec<u8> {
+    let info = DataIdInfo {
+        data_id: data_id.to_vec(),
+        data_type: RT::Other,
+        data_meta_hash: H256::repeat_byte(1),
+        data_hash: H256::repeat_byte(2),
+        owners: vec![],
+        did_owners,
+        threshold,
+        version: 0,
+        parents: vec![],
+    };
+    let mut sink = Sink::new(64);
+    sink.write(info);
+    sink.bytes().to_vec()
+}
+
+#[test]
+fn derived_with_dtoken_proof_of_did_owner() {
+    let handle = build_runtime();
+    let bob = Address::repeat_byte(2);
+    let key1 = Address::repeat_byte(1);
+    let did = b"did:ont:alice".to_vec();
+    handle.witness(&[ADMIN.clone()]);
+    assert!(set_identity_contract(&Address::repeat_byte(8)));
+    assert!(set_dtoken_contract(&Address::repeat_byte(7)));
+    handle.on_contract_call(|addr, data| {
+        if addr == &Address::repeat_byte(7) {
+            let mut sink = Sink::new(16);
+            sink.write(1u32);
+            sink.write(Vec::<Address>::new());
+            Some(sink.bytes().to_vec())
+        } else {
+            mock_identity_contract(addr, data)
+        }
+    });
+    handle.witness(&[bob.clone()]);
+    assert!(register_data_id(
+        &info_bytes(b"raw1", vec![bob.clone()], 1),
+        &[]
+    ));
+
+    handle.witness(&[key1.clone()]);
+    let clean = DataIdInfo {
+        did_owners: vec![did.clone()],
+        ..info(b"clean", vec![], vec![b"raw1".to_vec()])
+    };
+    let mut sink = Sink::new(64);
+    sink.write(clean);
+    let proof = ParentProof {
+        parent: b"raw1".to_vec(),
+        resource_id: b"resource1".to_vec(),
+        token_template: TokenTemplate::new(Some(b"raw1".to_vec()), vec![]),
+        holder: key1.clone(),
+    };
+    let signer = DidSigner {
+        did: did.clone(),
+        signer: key1.clone(),
+        key_index: 1,
+    };
+    assert!(register_derived_data_id(
+        sink.bytes(),
+        vec![proof],
+        &[signer]
+    ));
+    assert_eq!(get_parents(b"clean"), vec![b"raw1".to_vec()]);
+}
+
+/// stand-in identity contract, key 1 of did:ont:alice belongs to address 1, key 2 to address 2
+fn mock_identity_contract(_addr: &Address, data: &[u8]) -> Option<Vec<u8>> {
+    let mut source = Source::new(data);
+    let method: &[u8] = source.read().unwrap();
+    assert_eq!(method, b"verifyDid");
+    let (did, signer, key_index): (&[u8], Address, u32) = source.read().unwrap();
+    let controls = did == b"did:ont:alice"
+        && ((key_index == 1 && signer == Address::repeat_byte(1))
+            || (key_index == 2 && signer == Address::repeat_byte(2)));
+    let mut sink = Sink::new(4);
+    sink.write(controls);
+    Some(sink.bytes().to_vec())
+}
+
+#[test]
+fn did_owner() {
+    let handle = build_runtime();
+    let key1 = Address::repeat_byte(1);
+    let key2 = Address::repeat_byte(2);
+    let did = b"did:ont:alice".to_vec();
+    handle.witness(&[ADMIN.clone()]);
+    assert!(set_identity_contract(&Address::repeat_byte(8)));
+    handle.on_contract_call(mock_identity_contract);
+
+    handle.witness(&[key1.clone()]);
+    let signer = DidSigner {
+        did: did.clone(),
+        signer: key1.clone(),
+        key_index: 1,
+    };
+    assert!(register_data_id(
+        &did_info_bytes(b"data1", vec![did.clone()], 1),
+        &[signer]
+    ));
+    assert_eq!(get_data_ids_by_did(&did, 0, 10), vec![b"data1".to_vec()]);
+    let signer = DidSigner {
+        did: did.clone(),
+        signer: key1.clone(),
+        key_index: 1,
+    };
+    assert!(!is_owner(b"data1", &key1, &[]));
+    assert!(is_owner(b"data1", &key1, &[signer]));
+
+    // the DID rotated to key 2, the data id does not need to be registered again
+    handle.witness(&[key2.clone()]);
+    let signer = DidSigner {
+        did: did.clone(),
+        signer: key2.clone(),
+        key_index: 2,
+    };
+    assert!(publish_version(
+        b"data1",
+        H256::repeat_byte(3),
+        H256::repeat_byte(4),
+        &[signer]
+    ));
+}
+
+#[test]
+#[should_panic(expected = "not enough owner signatures")]
+fn did_owner_with_wrong_key() {
+    let handle = build_runtime();
+    let mallory = Address::repeat_byte(9);
+    let did = b"did:ont:alice".to_vec();
+    handle.witness(&[ADMIN.clone()]);
+    assert!(set_identity_contract(&Address::repeat_byte(8)));
+    handle.on_contract_call(mock_identity_contract);
+
+    handle.witness(&[mallory.clone()]);
+    let signer = DidSigner {
+        did: did.clone(),
+        signer: mallory.clone(),
+        key_index: 1,
+    };
+    register_data_id(&did_info_bytes(b"data1", vec![did], 1), &[signer]);
+}
+
+fn info(data_id: &[u8], owners: Vec<Address>, parents: Vec<Vec<u8>>) -> DataIdInfo {
+    let bytes = derived_bytes(data_id, owners, 1, parents);
+    let mut source = Source::new(&bytes);
+    source.read().unwrap()
+}
+
+#[test]
+fn register_batch() {
+    let handle = build_runtime();
+    let alice = Address::repeat_byte(1);
+    let bob = Address::repeat_byte(2);
+    handle.witness(&[alice.clone(), bob.clone()]);
+    assert!(register_data_ids(
+        vec![
+            info(b"file1", vec![alice.clone()], vec![]),
+            info(b"file2", vec![alice.clone()], vec![]),
+            info(b"file3", vec![bob.clone()], vec![b"file1".to_vec()]),
+        ],
+        &[]
+    ));
+    assert_eq!(
+        get_data_ids_by_owner(&alice, 0, 10),
+        vec![b"file1".to_vec(), b"file2".to_vec()]
+    );
+    assert_eq!(get_children(b"file1", 0, 10), vec![b"file3".to_vec()]);
+    assert_eq!(get_versions(b"file3", 0, 10).len(), 1);
+}
+
+static VERIFY_DID_CALLS: AtomicU32 = AtomicU32::new(0);
+
+/// the parent is owned by did:ont:alice, its consent is verified once for the whole batch
+#[test]
+fn register_batch_with_shared_parent() {
+    let handle = build_runtime();
+    let key1 = Address::repeat_byte(1);
+    let bob = Address::repeat_byte(2);
+    let did = b"did:ont:alice".to_vec();
+    handle.witness(&[ADMIN.clone()]);
+    assert!(set_identity_contract(&Address::repeat_byte(8)));
+    handle.on_contract_call(|addr, data| {
+        VERIFY_DID_CALLS.fetch_add(1, Ordering::SeqCst);
+        mock_identity_contract(addr, data)
+    });
+    let signer = || DidSigner {
+        did: did.clone(),
+        signer: key1.clone(),
+        key_index: 1,
+    };
+    handle.witness(&[key1.clone(), bob.clone()]);
+    assert!(register_data_id(
+        &did_info_bytes(b"raw", vec![did.clone()], 1),
+        &[signer()]
+    ));
+
+    VERIFY_DID_CALLS.store(0, Ordering::SeqCst);
+    let children = vec![b"clean1".to_vec(), b"clean2".to_vec(), b"clean3".to_vec()];
+    let infos = children
+        .iter()
+        .map(|id| info(id, vec![bob.clone()], vec![b"raw".to_vec()]))
+        .collect();
+    assert!(register_data_ids(infos, &[signer()]));
+    assert_eq!(VERIFY_DID_CALLS.load(Ordering::SeqCst), 1);
+    assert_eq!(get_children(b"raw", 0, 10), children);
+}
+
+#[test]
+fn register_large_batch() {
+    let handle = build_runtime();
+    let alice = Address::repeat_byte(1);
+    handle.witness(&[alice.clone()]);
+    let infos = (0..2000u32)
+        .map(|i| {
+            let data_id = [b"file".as_ref(), i.to_le_bytes().as_ref()].concat();
+            info(&data_id, vec![alice.clone()], vec![])
+        })
+        .collect();
+    assert!(register_data_ids(infos, &[]));
+    assert_eq!(get_owner_index_count(&alice), 2000);
+    let last = get_data_ids_by_owner(&alice, 1990, 100);
+    assert_eq!(last.len(), 10);
+    assert_eq!(
+        last[9],
+        [b"file".as_ref(), 1999u32.to_le_bytes().as_ref()].concat()
+    );
+}
+
+#[test]
+#[should_panic(expected = "not enough owner signatures")]
+fn register_batch_without_all_owners() {
+    let handle = build_runtime();
+    let alice = Address::repeat_byte(1);
+    let bob = Address::repeat_byte(2);
+    handle.witness(&[alice.clone()]);
+    register_data_ids(
+        vec![
+            info(b"file1", vec![alice.clone()], vec![]),
+            info(b"file2", vec![bob.clone()], vec![]),
+        ],
+        &[],
+    );
+}
+
+#[test]
+#[should_panic(expected = "duplicate data id")]
+fn register_batch_with_duplicates() {
+    let handle = build_runtime();
+    let alice = Address::repeat_byte(1);
+    handle.witness(&[alice.clone()]);
+    register_data_ids(
+        vec![
+            info(b"file1", vec![alice.clone()], vec![]),
+            info(b"file1", vec![alice.clone()], vec![]),
+        ],
+        &[],
+    );
+}
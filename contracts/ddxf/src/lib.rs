@@ -2,7 +2,8 @@
 //!
 //! Seller publishing process
 //!
-//! seller only need invoke [`dtoken_seller_publish`](fn.dtoken_seller_publish.html) method to publish products
+//! seller only need invoke [`dtoken_seller_publish`](fn.dtoken_seller_publish.html) method to publish products,
+//! or [`dtoken_seller_publish_with_template`](fn.dtoken_seller_publish_with_template.html) to reuse a split template
 //!
 //! Buyer purchase and use process
 //!
@@ -15,7 +16,7 @@
 extern crate alloc;
 extern crate common;
 extern crate ontio_std as ostd;
-use ostd::abi::{EventBuilder, Sink, Source};
+use ostd::abi::{Encoder, EventBuilder, Sink, Source};
 use ostd::database;
 use ostd::prelude::*;
 use ostd::runtime;
@@ -39,6 +40,11 @@ const KEY_SELLER_ITEM_SOLD: &[u8] = b"02";
 const KEY_DTOKEN_CONTRACT: &[u8] = b"03";
 const KEY_SPLIT_POLICY_CONTRACT: &[u8] = b"04";
 const KEY_ADMIN: &[u8] = b"05";
+const KEY_REGISTRY_CONTRACT: &[u8] = b"06";
+const KEY_REQUIRE_ENDORSEMENT: &[u8] = b"07";
+const KEY_DATA_ID_CONTRACT: &[u8] = b"08";
+const KEY_CHECK_REVOKED_ON_USE: &[u8] = b"09";
+const KEY_DATA_VERSION: &[u8] = b"10";
 
 const ADMIN: Address = ostd::macros::base58!("AYnhakv7kC9R5ppw65JoE2rt6xDzCjCTvD");
 const DEFAULT_SPLIT_CONTRACT: Address = ostd::macros::base58!("AYnhakv7kC9R5ppw65JoE2rt6xDzCjCTvD");
@@ -85,6 +91,49 @@ fn get_split_policy_contract() -> Address {
     database::get::<_, Address>(KEY_SPLIT_POLICY_CONTRACT).unwrap_or(DEFAULT_SPLIT_CONTRACT)
 }
 
+/// set registry contract address, need admin signature
+///
+/// once it is set, the marketplace of a published item must be registered and active in the registry
+pub fn set_registry_contract(new_addr: &Address) -> bool {
+    assert!(check_witness(&get_admin()));
+    database::put(KEY_REGISTRY_CONTRACT, new_addr);
+    true
+}
+
+/// query the registry contract address, None means marketplaces are not checked
+fn get_registry_contract() -> Option<Address> {
+    database::get::<_, Address>(KEY_REGISTRY_CONTRACT)
+}
+
+/// set whether items routed through a marketplace must be endorsed by it before they can be bought
+///
+/// need admin signature
+pub fn set_require_endorsement(required: bool) -> bool {
+    assert!(check_witness(&get_admin()));
+    database::put(KEY_REQUIRE_ENDORSEMENT, required);
+    true
+}
+
+/// query whether purchases require marketplace endorsement
+fn get_require_endorsement() -> bool {
+    database::get::<_, bool>(KEY_REQUIRE_ENDORSEMENT).unwrap_or(false)
+}
+
+/// set data id contract address, need admin signature
+///
+/// a template with data_id can only be published by an owner of the data id,
+/// and items whose templates refer to a revoked data id can not be published or sold
+pub fn set_data_id_contract(new_addr: &Address) -> bool {
+    assert!(check_witness(&get_admin()));
+    database::put(KEY_DATA_ID_CONTRACT, new_addr);
+    true
+}
+
+/// query the data id contract address, None means templates with data_id can not be published
+fn get_data_id_contract() -> Option<Address> {
+    database::get::<_, Address>(KEY_DATA_ID_CONTRACT)
+}
+
 /// need old admin signature
 ///
 /// update the admin address, admin has the right to set the default dtoken and split contract address
@@ -110,6 +159,9 @@ fn get_admin() -> Address {
 ///
 /// `split_policy_param_bytes` is the result of RegisterParam struct serialization
 ///
+/// `did_signers` sign for the DID owners of the data ids in the templates, it is empty when the
+/// seller owns them by address
+///
 /// # Example
 /// ```no_run
 /// use common::{Fee,TokenType};
@@ -141,7 +193,8 @@ fn get_admin() -> Address {
 ///        resource_id,
 ///        &ddo.to_bytes(),
 ///        &dtoken_item.to_bytes(),
-///        split_param
+///        split_param,
+///        vec![]
 ///    ));
 /// ```
 pub fn dtoken_seller_publish(
@@ -149,7 +202,82 @@ pub fn dtoken_seller_publish(
     resource_ddo_bytes: &[u8],
     item_bytes: &[u8],
     split_policy_param_bytes: &[u8],
+    did_signers: Vec<DidSigner>,
 ) -> bool {
+    let (resource_ddo, item) =
+        save_seller_item(resource_id, resource_ddo_bytes, item_bytes, did_signers);
+
+    //invoke split_policy contract
+    let split_addr = get_split_policy_contract();
+    let res = wasm::call_contract(
+        &resource_ddo
+            .split_policy_contract_address
+            .unwrap_or(split_addr),
+        ("register", (resource_id, split_policy_param_bytes)),
+    );
+    check_split_result(res, "register");
+
+    publish_event(resource_id, resource_ddo, item);
+    true
+}
+
+/// seller publish product with a split template registered in the split contract, need seller signature
+///
+/// the resource is bound to the template instead of registering its own RegisterParam,
+/// the template owner must sign as well
+///
+/// `template_id` is the id of the template in the split contract
+///
+/// `snapshot` copies the current terms of the template, otherwise the resource follows the template
+pub fn dtoken_seller_publish_with_template(
+    resource_id: &[u8],
+    resource_ddo_bytes: &[u8],
+    item_bytes: &[u8],
+    template_id: &[u8],
+    snapshot: bool,
+    did_signers: Vec<DidSigner>,
+) -> bool {
+    let (resource_ddo, item) =
+        save_seller_item(resource_id, resource_ddo_bytes, item_bytes, did_signers);
+
+    let split_addr = get_split_policy_contract();
+    let res = wasm::call_contract(
+        &resource_ddo
+            .split_policy_contract_address
+            .unwrap_or(split_addr),
+        ("bindTemplate", (resource_id, template_id, snapshot)),
+    );
+    check_split_result(res, "bindTemplate");
+
+    publish_event(resource_id, resource_ddo, item);
+    true
+}
+
+fn check_split_result(res: Option<Vec<u8>>, method: &str) {
+    if let Some(r) = res {
+        let mut source = Source::new(r.as_slice());
+        let rr: bool = source.read().unwrap();
+        assert!(rr);
+    } else {
+        panic!("call split contract {} failed", method);
+    }
+}
+
+fn publish_event(resource_id: &[u8], resource_ddo: ResourceDDO, item: DTokenItem) {
+    let mut sink = Sink::new(16);
+    sink.write(resource_ddo);
+    let mut sink2 = Sink::new(16);
+    sink2.write(item);
+    events::dtoken_seller_publish_event(resource_id, sink.bytes(), sink2.bytes());
+}
+
+/// check the published item and save it, need seller signature
+fn save_seller_item(
+    resource_id: &[u8],
+    resource_ddo_bytes: &[u8],
+    item_bytes: &[u8],
+    did_signers: Vec<DidSigner>,
+) -> (ResourceDDO, DTokenItem) {
     let resource_ddo = ResourceDDO::from_bytes(resource_ddo_bytes);
     let item = DTokenItem::from_bytes(item_bytes);
     assert!(runtime::check_witness(&resource_ddo.manager));
@@ -179,33 +307,26 @@ pub fn dtoken_seller_publish(
             }
         }
     }
+    if let (Some(registry), Some(mp)) = (
+        get_registry_contract(),
+        resource_ddo.mp_contract_address.as_ref(),
+    ) {
+        let res = wasm::call_contract(&registry, ("isRegistered", mp));
+        if let Some(r) = res {
+            let mut source = Source::new(r.as_slice());
+            let registered: bool = source.read().unwrap();
+            assert!(registered, "marketplace is not registered");
+        } else {
+            panic!("call registry contract failed");
+        }
+    }
+
+    check_data_id_owner(&item.templates, &resource_ddo.manager, did_signers);
+    check_not_revoked(&item.templates);
 
     let seller = SellerItemInfo::new(item.clone(), resource_ddo.clone());
     database::put(utils::generate_seller_item_info_key(resource_id), seller);
-
-    //invoke split_policy contract
-    let split_addr = get_split_policy_contract();
-    let res = wasm::call_contract(
-        &resource_ddo
-            .split_policy_contract_address
-            .unwrap_or(split_addr),
-        ("register", (resource_id, split_policy_param_bytes)),
-    );
-    if let Some(r) = res {
-        let mut source = Source::new(r.as_slice());
-        let rr: bool = source.read().unwrap();
-        assert!(rr);
-    } else {
-        panic!("call split contract register failed");
-    }
-
-    //event
-    let mut sink = Sink::new(16);
-    sink.write(resource_ddo);
-    let mut sink2 = Sink::new(16);
-    sink2.write(item);
-    events::dtoken_seller_publish_event(resource_id, sink.bytes(), sink2.bytes());
-    true
+    (resource_ddo, item)
 }
 
 /// buy dtoken from reseller
@@ -229,6 +350,8 @@ pub fn buy_dtoken_from_reseller(
     let item_info =
         database::get::<_, SellerItemInfo>(utils::generate_seller_item_info_key(resource_id))
             .unwrap();
+    check_endorsement(resource_id, &item_info.resource_ddo.mp_contract_address);
+    check_not_revoked(&item_info.item.templates);
     let oi = OrderId {
         item_id: resource_id.to_vec(),
         tx_hash: current_txhash(),
@@ -237,6 +360,7 @@ pub fn buy_dtoken_from_reseller(
     assert!(transfer_fee(
         &oi,
         buyer_account,
+        &item_info.resource_ddo.manager,
         item_info.resource_ddo.mp_contract_address,
         &item_info
             .resource_ddo
@@ -340,6 +464,8 @@ pub fn buy_dtoken(resource_id: &[u8], n: U128, buyer_account: &Address) -> bool
             .unwrap();
     let now = runtime::timestamp();
     assert!(now < item_info.item.expired_date);
+    check_endorsement(resource_id, &item_info.resource_ddo.mp_contract_address);
+    check_not_revoked(&item_info.item.templates);
     let sold =
         database::get::<_, U128>(utils::generate_seller_item_sold_key(resource_id)).unwrap_or(0);
     assert!(sold < item_info.item.stocks as U128);
@@ -352,6 +478,7 @@ pub fn buy_dtoken(resource_id: &[u8], n: U128, buyer_account: &Address) -> bool
     assert!(transfer_fee(
         &oi,
         buyer_account,
+        &item_info.resource_ddo.manager,
         item_info.resource_ddo.mp_contract_address.clone(),
         &item_info
             .resource_ddo
@@ -401,6 +528,7 @@ pub fn use_token(
     let item_info =
         database::get::<_, SellerItemInfo>(utils::generate_seller_item_info_key(resource_id))
             .unwrap();
+    check_use_not_revoked(resource_id, token_template_bytes);
     let dtoken = get_dtoken_contract();
     assert!(use_token_dtoken(
         &item_info
@@ -444,6 +572,7 @@ pub fn use_token_by_agent(
     let item_info =
         database::get::<_, SellerItemInfo>(utils::generate_seller_item_info_key(resource_id))
             .unwrap();
+    check_use_not_revoked(resource_id, token_template_bytes);
     let dtoken = get_dtoken_contract();
     assert!(use_token_by_agent_dtoken(
         &item_info
@@ -705,9 +834,164 @@ fn migrate(
     true
 }
 
+fn check_endorsement(resource_id: &[u8], mp_contract_address: &Option<Address>) {
+    if !get_require_endorsement() {
+        return;
+    }
+    if let Some(mp_addr) = mp_contract_address {
+        let res = wasm::call_contract(mp_addr, ("isEndorsed", resource_id));
+        if let Some(r) = res {
+            let mut source = Source::new(r.as_slice());
+            let endorsed: bool = source.read().unwrap();
+            assert!(endorsed, "item is not endorsed by the marketplace");
+        } else {
+            panic!("call marketplace contract failed");
+        }
+    }
+}
+
+/// seller decides whether tokens of a revoked data id can still be used, need seller signature
+///
+/// by default, tokens already bought can be used after the data id is revoked
+pub fn set_check_revoked_on_use(resource_id: &[u8], check: bool) -> bool {
+    let item_info =
+        database::get::<_, SellerItemInfo>(utils::generate_seller_item_info_key(resource_id))
+            .unwrap();
+    assert!(check_witness(&item_info.resource_ddo.manager));
+    database::put(utils::generate_check_revoked_on_use_key(resource_id), check);
+    true
+}
+
+/// query whether use_token fails for a revoked data id
+fn get_check_revoked_on_use(resource_id: &[u8]) -> bool {
+    database::get::<_, bool>(utils::generate_check_revoked_on_use_key(resource_id)).unwrap_or(false)
+}
+
+fn check_use_not_revoked(resource_id: &[u8], token_template_bytes: &[u8]) {
+    if get_check_revoked_on_use(resource_id) {
+        check_not_revoked(&[TokenTemplate::from_bytes(token_template_bytes)]);
+    }
+}
+
+/// seller pins the version of the data id a token template licenses, need seller signature
+///
+/// the version must be published in the data id contract, and the pin can only be set
+/// before the first token of the item is sold, a template without a pin follows the latest version
+pub fn pin_data_version(resource_id: &[u8], token_template_bytes: &[u8], version: u32) -> bool {
+    let item_info =
+        database::get::<_, SellerItemInfo>(utils::generate_seller_item_info_key(resource_id))
+            .unwrap();
+    assert!(check_witness(&item_info.resource_ddo.manager));
+    let template = TokenTemplate::from_bytes(token_template_bytes);
+    assert!(
+        item_info.item.templates.contains(&template),
+        "token template not found"
+    );
+    let data_id = template
+        .data_id
+        .as_ref()
+        .expect("token template has no data id");
+    let sold =
+        database::get::<_, U128>(utils::generate_seller_item_sold_key(resource_id)).unwrap_or(0);
+    assert_eq!(sold, 0, "item is already sold");
+    let key = utils::generate_data_version_key(resource_id, &template.to_bytes());
+    assert!(
+        database::get::<_, u32>(&key).is_none(),
+        "data version is already pinned"
+    );
+    let data_id_contract = get_data_id_contract().expect("data id contract not set");
+    assert!(
+        query_data_id(
+            &data_id_contract,
+            "hasVersion",
+            (data_id.as_slice(), version)
+        ),
+        "data version not published"
+    );
+    database::put(key, version);
+    EventBuilder::new()
+        .string("pinDataVersion")
+        .bytearray(resource_id)
+        .bytearray(token_template_bytes)
+        .number(version as U128)
+        .notify();
+    true
+}
+
+/// query the version of the data id the token template licenses, None follows the latest version
+pub fn get_data_version(resource_id: &[u8], token_template_bytes: &[u8]) -> Option<u32> {
+    let template = TokenTemplate::from_bytes(token_template_bytes);
+    database::get::<_, u32>(utils::generate_data_version_key(
+        resource_id,
+        &template.to_bytes(),
+    ))
+}
+
+/// every data id of the templates must be registered and owned by the seller,
+/// and `threshold` of its owners must sign the publishing
+///
+/// the seller owns a data id by address, or by signing for one of its DID owners
+fn check_data_id_owner(
+    templates: &[TokenTemplate],
+    manager: &Address,
+    did_signers: Vec<DidSigner>,
+) {
+    let mut data_ids = templates
+        .iter()
+        .filter_map(|t| t.data_id.as_ref())
+        .peekable();
+    if data_ids.peek().is_none() {
+        return;
+    }
+    let data_id_contract = get_data_id_contract().expect("data id contract not set");
+    for data_id in data_ids {
+        assert!(
+            query_data_id(
+                &data_id_contract,
+                "isOwner",
+                (data_id.as_slice(), manager, &did_signers)
+            ),
+            "seller is not the data id owner"
+        );
+        assert!(
+            query_data_id(
+                &data_id_contract,
+                "isSignedByOwners",
+                (data_id.as_slice(), &did_signers)
+            ),
+            "not enough data id owner signatures"
+        );
+    }
+}
+
+fn query_data_id<T: Encoder>(data_id_contract: &Address, method: &str, args: T) -> bool {
+    let res = wasm::call_contract(data_id_contract, (method, args));
+    if let Some(r) = res {
+        let mut source = Source::new(r.as_slice());
+        source.read().unwrap()
+    } else {
+        panic!("call data id contract failed");
+    }
+}
+
+fn check_not_revoked(templates: &[TokenTemplate]) {
+    let data_id_contract = match get_data_id_contract() {
+        Some(addr) => addr,
+        None => return,
+    };
+    for data_id in templates.iter().filter_map(|t| t.data_id.as_ref()) {
+        assert!(
+            !query_data_id(&data_id_contract, "isRevoked", data_id.as_slice()),
+            "data id is revoked"
+        );
+    }
+}
+
+/// the seller is passed to the marketplace, so that only the seller can settle the order
 fn transfer_fee(
     oi: &OrderId,
     buyer_account: &Address,
+    seller: &Address,
     mp_contract_address: Option<Address>,
     split_contract_address: &Address,
     fee: Fee,
@@ -718,7 +1002,14 @@ fn transfer_fee(
             &mp_addr,
             (
                 "transferAmount",
-                (oi.to_bytes(), buyer_account, split_contract_address, fee, n),
+                (
+                    oi.to_bytes(),
+                    buyer_account,
+                    split_contract_address,
+                    fee,
+                    n,
+                    seller,
+                ),
             ),
         ),
         _ => {
@@ -726,10 +1017,21 @@ fn transfer_fee(
             wasm::call_contract(
                 split_contract_address,
                 (
-                    "transferWithdraw",
-                    (oi.item_id.as_slice(), buyer_account, amt),
+                    "transferWithdrawCurrency",
+                    (buyer_account, oi.item_id.as_slice(), fee.currency(), amt),
                 ),
             )
+            .or_else(|| {
+                // split contracts deployed before the per currency accounting only know
+                // the former method, which transfers in the one token of the policy
+                wasm::call_contract(
+                    split_contract_address,
+                    (
+                        "transferWithdraw",
+                        (buyer_account, oi.item_id.as_slice(), amt),
+                    ),
+                )
+            })
         }
     };
     if let Some(rr) = res {
@@ -772,6 +1074,43 @@ pub fn invoke() {
         b"getDTokenContract" => {
             sink.write(get_dtoken_contract());
         }
+        b"setRegistryContract" => {
+            let new_addr = source.read().unwrap();
+            sink.write(set_registry_contract(new_addr));
+        }
+        b"getRegistryContract" => {
+            sink.write(get_registry_contract());
+        }
+        b"setRequireEndorsement" => {
+            let required = source.read().unwrap();
+            sink.write(set_require_endorsement(required));
+        }
+        b"getRequireEndorsement" => {
+            sink.write(get_require_endorsement());
+        }
+        b"setDataIdContract" => {
+            let new_addr = source.read().unwrap();
+            sink.write(set_data_id_contract(new_addr));
+        }
+        b"getDataIdContract" => {
+            sink.write(get_data_id_contract());
+        }
+        b"setCheckRevokedOnUse" => {
+            let (resource_id, check) = source.read().unwrap();
+            sink.write(set_check_revoked_on_use(resource_id, check));
+        }
+        b"getCheckRevokedOnUse" => {
+            let resource_id = source.read().unwrap();
+            sink.write(get_check_revoked_on_use(resource_id));
+        }
+        b"pinDataVersion" => {
+            let (resource_id, token_template_bytes, version) = source.read().unwrap();
+            sink.write(pin_data_version(resource_id, token_template_bytes, version));
+        }
+        b"getDataVersion" => {
+            let (resource_id, token_template_bytes) = source.read().unwrap();
+            sink.write(get_data_version(resource_id, token_template_bytes));
+        }
         b"migrate" => {
             let (code, vm_type, name, version, author, email, desc) = source.read().unwrap();
             sink.write(migrate(code, vm_type, name, version, author, email, desc));
@@ -779,11 +1118,26 @@ pub fn invoke() {
         b"dtokenSellerPublish" => {
             let (resource_id, resource_ddo, item, split_policy_param_bytes) =
                 source.read().unwrap();
+            let did_signers = read_did_signers(&mut source);
             sink.write(dtoken_seller_publish(
                 resource_id,
                 resource_ddo,
                 item,
                 split_policy_param_bytes,
+                did_signers,
+            ));
+        }
+        b"dtokenSellerPublishWithTemplate" => {
+            let (resource_id, resource_ddo, item, template_id, snapshot) =
+                source.read().unwrap();
+            let did_signers = read_did_signers(&mut source);
+            sink.write(dtoken_seller_publish_with_template(
+                resource_id,
+                resource_ddo,
+                item,
+                template_id,
+                snapshot,
+                did_signers,
             ));
         }
         b"buyDtokenFromReseller" => {
@@ -931,6 +1285,18 @@ mod utils {
     pub fn generate_seller_item_sold_key(resource_id: &[u8]) -> Vec<u8> {
         [KEY_SELLER_ITEM_SOLD, resource_id].concat()
     }
+    pub fn generate_check_revoked_on_use_key(resource_id: &[u8]) -> Vec<u8> {
+        [KEY_CHECK_REVOKED_ON_USE, resource_id].concat()
+    }
+    pub fn generate_data_version_key(resource_id: &[u8], token_template: &[u8]) -> Vec<u8> {
+        [
+            KEY_DATA_VERSION,
+            (resource_id.len() as u32).to_le_bytes().as_ref(),
+            resource_id,
+            token_template,
+        ]
+        .concat()
+    }
 }
 
 #[cfg(test)]
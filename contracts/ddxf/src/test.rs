@@ -191,7 +191,8 @@ fn publish() {
         resource_id,
         &ddo.to_bytes(),
         &dtoken_item.to_bytes(),
-        split_param
+        split_param,
+        vec![]
     ));
 
     assert!(set_dtoken_contract(&dtoken_contract_address));
@@ -224,6 +225,529 @@ fn publish() {
     assert!(use_token(resource_id, &buyer2, &token_template_bytes, 1));
 }
 
+/// publish through the marketplace `mp`, the mock registry reports it as `registered`
+fn publish_through_marketplace(registered: bool) -> bool {
+    let manager = Address::repeat_byte(1);
+    let registry = Address::repeat_byte(6);
+    let mp = Address::repeat_byte(7);
+    let ddo = ResourceDDO {
+        token_resource_ty_endpoints: vec![],
+        item_meta_hash: H256::repeat_byte(1),
+        manager: manager.clone(),
+        dtoken_contract_address: None,
+        mp_contract_address: Some(mp.clone()),
+        split_policy_contract_address: None,
+    };
+    let item = DTokenItem {
+        fee: Fee::default(),
+        expired_date: 1,
+        stocks: 1,
+        templates: vec![TokenTemplate::new(None, vec![vec![0u8; 32]])],
+    };
+
+    let handle = build_runtime();
+    handle.witness(&[manager.clone(), ADMIN.clone()]);
+    assert!(set_registry_contract(&registry));
+    handle.on_contract_call(move |addr, data| {
+        let mut source = Source::new(data);
+        let method: &[u8] = source.read().unwrap();
+        let mut sink = Sink::new(4);
+        if addr == &registry {
+            assert_eq!(method, b"isRegistered");
+            let queried: Address = source.read().unwrap();
+            sink.write(registered && queried == mp);
+        } else {
+            assert_eq!(method, b"register");
+            sink.write(true);
+        }
+        Some(sink.bytes().to_vec())
+    });
+    dtoken_seller_publish(
+        b"resource_id",
+        &ddo.to_bytes(),
+        &item.to_bytes(),
+        b"test",
+        vec![],
+    )
+}
+
+#[test]
+fn publish_through_registered_marketplace() {
+    assert!(publish_through_marketplace(true));
+}
+
+#[test]
+#[should_panic(expected = "marketplace is not registered")]
+fn publish_through_unregistered_marketplace() {
+    publish_through_marketplace(false);
+}
+
+/// endorsement is required, buy an item published through the marketplace `mp`,
+/// which reports the item as `endorsed`
+fn buy_through_marketplace(endorsed: bool) -> bool {
+    let manager = Address::repeat_byte(1);
+    let buyer = Address::repeat_byte(4);
+    let mp = Address::repeat_byte(7);
+    let ddo = ResourceDDO {
+        token_resource_ty_endpoints: vec![],
+        item_meta_hash: H256::repeat_byte(1),
+        manager: manager.clone(),
+        dtoken_contract_address: None,
+        mp_contract_address: Some(mp.clone()),
+        split_policy_contract_address: None,
+    };
+    let item = DTokenItem {
+        fee: Fee::default(),
+        expired_date: 1,
+        stocks: 1,
+        templates: vec![TokenTemplate::new(None, vec![vec![0u8; 32]])],
+    };
+
+    let handle = build_runtime();
+    handle.witness(&[manager.clone(), ADMIN.clone()]);
+    assert!(set_require_endorsement(true));
+    handle.on_contract_call(move |addr, data| {
+        let mut source = Source::new(data);
+        let method: &[u8] = source.read().unwrap();
+        let mut sink = Sink::new(4);
+        if addr == &mp && method == b"isEndorsed" {
+            let resource_id: &[u8] = source.read().unwrap();
+            sink.write(endorsed && resource_id == b"resource_id");
+        } else {
+            sink.write(true);
+        }
+        Some(sink.bytes().to_vec())
+    });
+    assert!(dtoken_seller_publish(
+        b"resource_id",
+        &ddo.to_bytes(),
+        &item.to_bytes(),
+        b"test",
+        vec![],
+    ));
+    handle.witness(&[buyer.clone()]);
+    buy_dtoken(b"resource_id", 1, &buyer)
+}
+
+#[test]
+fn buy_through_endorsing_marketplace() {
+    assert!(buy_through_marketplace(true));
+}
+
+#[test]
+#[should_panic(expected = "item is not endorsed by the marketplace")]
+fn buy_through_unendorsing_marketplace() {
+    buy_through_marketplace(false);
+}
+
+#[test]
+fn publish_with_template() {
+    let manager = Address::repeat_byte(1);
+    let split = Address::repeat_byte(8);
+    let ddo = ResourceDDO {
+        token_resource_ty_endpoints: vec![],
+        item_meta_hash: H256::repeat_byte(1),
+        manager: manager.clone(),
+        dtoken_contract_address: None,
+        mp_contract_address: None,
+        split_policy_contract_address: Some(split.clone()),
+    };
+    let item = DTokenItem {
+        fee: Fee::default(),
+        expired_date: 1,
+        stocks: 10,
+        templates: vec![TokenTemplate::new(None, vec![vec![0u8; 32]])],
+    };
+
+    let handle = build_runtime();
+    handle.witness(&[manager.clone()]);
+    handle.on_contract_call(move |addr, data| {
+        assert_eq!(addr, &split);
+        let mut source = Source::new(data);
+        let method: &[u8] = source.read().unwrap();
+        assert_eq!(method, b"bindTemplate");
+        let (resource_id, template_id, snapshot): (&[u8], &[u8], bool) = source.read().unwrap();
+        assert_eq!(resource_id, b"resource_id");
+        assert_eq!(template_id, b"template_id");
+        assert!(snapshot);
+        let mut sink = Sink::new(4);
+        sink.write(true);
+        Some(sink.bytes().to_vec())
+    });
+    assert!(dtoken_seller_publish_with_template(
+        b"resource_id",
+        &ddo.to_bytes(),
+        &item.to_bytes(),
+        b"template_id",
+        true,
+        vec![],
+    ));
+
+    let stored =
+        database::get::<_, SellerItemInfo>(utils::generate_seller_item_info_key(b"resource_id"))
+            .unwrap();
+    assert_eq!(stored.item.to_bytes(), item.to_bytes());
+    assert_eq!(stored.resource_ddo.to_bytes(), ddo.to_bytes());
+}
+
+/// the split contract only knows `transferWithdraw`, the fee is paid through it
+#[test]
+fn buy_through_legacy_split_contract() {
+    let manager = Address::repeat_byte(1);
+    let buyer = Address::repeat_byte(4);
+    let split = Address::repeat_byte(8);
+    let ddo = ResourceDDO {
+        token_resource_ty_endpoints: vec![],
+        item_meta_hash: H256::repeat_byte(1),
+        manager: manager.clone(),
+        dtoken_contract_address: None,
+        mp_contract_address: None,
+        split_policy_contract_address: Some(split.clone()),
+    };
+    let item = DTokenItem {
+        fee: Fee {
+            contract_addr: Address::repeat_byte(0),
+            contract_type: TokenType::ONG,
+            count: 10,
+        },
+        expired_date: 1,
+        stocks: 10,
+        templates: vec![TokenTemplate::new(None, vec![vec![0u8; 32]])],
+    };
+
+    let handle = build_runtime();
+    handle.witness(&[manager.clone()]);
+    let payer = buyer.clone();
+    handle.on_contract_call(move |addr, data| {
+        let mut source = Source::new(data);
+        let method: &[u8] = source.read().unwrap();
+        if addr == &split && method == b"transferWithdrawCurrency" {
+            return None;
+        }
+        if addr == &split && method == b"transferWithdraw" {
+            let (from, key, amt): (Address, &[u8], U128) = source.read().unwrap();
+            assert_eq!(from, payer);
+            assert_eq!(key, b"resource_id");
+            assert_eq!(amt, 20);
+        }
+        let mut sink = Sink::new(4);
+        sink.write(true);
+        Some(sink.bytes().to_vec())
+    });
+    assert!(dtoken_seller_publish(
+        b"resource_id",
+        &ddo.to_bytes(),
+        &item.to_bytes(),
+        b"test",
+        vec![],
+    ));
+    handle.witness(&[buyer.clone()]);
+    assert!(buy_dtoken(b"resource_id", 2, &buyer));
+}
+
+fn data_id_ddo(manager: &Address) -> ResourceDDO {
+    ResourceDDO {
+        token_resource_ty_endpoints: vec![],
+        item_meta_hash: H256::repeat_byte(1),
+        manager: manager.clone(),
+        dtoken_contract_address: None,
+        mp_contract_address: None,
+        split_policy_contract_address: None,
+    }
+}
+
+fn data_id_item() -> DTokenItem {
+    DTokenItem {
+        fee: Fee::default(),
+        expired_date: 1,
+        stocks: 1,
+        templates: vec![TokenTemplate::new(
+            Some(b"data_id".to_vec()),
+            vec![vec![0u8; 32]],
+        )],
+    }
+}
+
+/// mock data id contract, `owner` owns every data id, versions 1 and 2 are published
+///
+/// `signed` tells whether enough owners signed
+fn mock_data_id_contract(
+    data: &[u8],
+    owner: &Address,
+    revoked: bool,
+    signed: bool,
+) -> Option<Vec<u8>> {
+    let mut source = Source::new(data);
+    let method: &[u8] = source.read().unwrap();
+    let mut sink = Sink::new(16);
+    match method {
+        b"isOwner" => {
+            let (_data_id, addr): (&[u8], Address) = source.read().unwrap();
+            sink.write(&addr == owner)
+        }
+        b"isRevoked" => sink.write(revoked),
+        b"isSignedByOwners" => sink.write(signed),
+        b"hasVersion" => {
+            let (_data_id, version): (&[u8], u32) = source.read().unwrap();
+            sink.write(version <= 2)
+        }
+        _ => return None,
+    }
+    Some(sink.bytes().to_vec())
+}
+
+#[test]
+#[should_panic(expected = "data id is revoked")]
+fn publish_revoked_data_id() {
+    let manager = Address::repeat_byte(1);
+    let data_id_contract = Address::repeat_byte(6);
+
+    let handle = build_runtime();
+    handle.witness(&[manager.clone(), ADMIN.clone()]);
+    assert!(set_data_id_contract(&data_id_contract));
+    let owner = manager.clone();
+    handle.on_contract_call(move |addr, data| {
+        assert_eq!(addr, &data_id_contract);
+        mock_data_id_contract(data, &owner, true, true)
+    });
+    dtoken_seller_publish(
+        b"resource_id",
+        &data_id_ddo(&manager).to_bytes(),
+        &data_id_item().to_bytes(),
+        b"test",
+        vec![],
+    );
+}
+
+#[test]
+#[should_panic(expected = "seller is not the data id owner")]
+fn publish_data_id_of_others() {
+    let manager = Address::repeat_byte(1);
+    let data_id_contract = Address::repeat_byte(6);
+
+    let handle = build_runtime();
+    handle.witness(&[manager.clone(), ADMIN.clone()]);
+    assert!(set_data_id_contract(&data_id_contract));
+    handle.on_contract_call(move |_addr, data| {
+        mock_data_id_contract(data, &Address::repeat_byte(9), false, true)
+    });
+    dtoken_seller_publish(
+        b"resource_id",
+        &data_id_ddo(&manager).to_bytes(),
+        &data_id_item().to_bytes(),
+        b"test",
+        vec![],
+    );
+}
+
+/// the data id has a threshold of 2, only the seller signed
+#[test]
+#[should_panic(expected = "not enough data id owner signatures")]
+fn publish_data_id_below_threshold() {
+    let manager = Address::repeat_byte(1);
+    let data_id_contract = Address::repeat_byte(6);
+
+    let handle = build_runtime();
+    handle.witness(&[manager.clone(), ADMIN.clone()]);
+    assert!(set_data_id_contract(&data_id_contract));
+    handle.witness(&[manager.clone()]);
+    let owner = manager.clone();
+    handle.on_contract_call(move |_addr, data| mock_data_id_contract(data, &owner, false, false));
+    dtoken_seller_publish(
+        b"resource_id",
+        &data_id_ddo(&manager).to_bytes(),
+        &data_id_item().to_bytes(),
+        b"test",
+        vec![],
+    );
+}
+
+/// the data id is owned by did:ont:alice, the seller signs for it
+#[test]
+fn publish_data_id_of_did_owner() {
+    let manager = Address::repeat_byte(1);
+    let data_id_contract = Address::repeat_byte(6);
+
+    let handle = build_runtime();
+    handle.witness(&[manager.clone(), ADMIN.clone()]);
+    assert!(set_data_id_contract(&data_id_contract));
+    handle.on_contract_call(move |addr, data| {
+        let mut source = Source::new(data);
+        let method: &[u8] = source.read().unwrap();
+        let mut sink = Sink::new(4);
+        match method {
+            _ if addr != &data_id_contract => sink.write(true),
+            b"isOwner" => {
+                let (_data_id, owner, did_signers): (&[u8], Address, Vec<DidSigner>) =
+                    source.read().unwrap();
+                sink.write(
+                    did_signers
+                        .iter()
+                        .any(|ds| ds.signer == owner && ds.did == b"did:ont:alice"),
+                )
+            }
+            b"isSignedByOwners" => {
+                let (_data_id, did_signers): (&[u8], Vec<DidSigner>) = source.read().unwrap();
+                sink.write(did_signers.len() == 1)
+            }
+            _ => return mock_data_id_contract(data, &Address::repeat_byte(9), false, false),
+        }
+        Some(sink.bytes().to_vec())
+    });
+    let signer = DidSigner {
+        did: b"did:ont:alice".to_vec(),
+        signer: manager.clone(),
+        key_index: 1,
+    };
+    assert!(dtoken_seller_publish(
+        b"resource_id",
+        &data_id_ddo(&manager).to_bytes(),
+        &data_id_item().to_bytes(),
+        b"test",
+        vec![signer],
+    ));
+}
+
+/// publish `data_id_item` and pin its template to `version`
+fn pin_published_version(version: u32) -> bool {
+    let manager = Address::repeat_byte(1);
+    let data_id_contract = Address::repeat_byte(6);
+
+    let handle = build_runtime();
+    handle.witness(&[manager.clone(), ADMIN.clone()]);
+    assert!(set_data_id_contract(&data_id_contract));
+    let owner = manager.clone();
+    handle.on_contract_call(move |addr, data| {
+        if addr == &data_id_contract {
+            mock_data_id_contract(data, &owner, false, true)
+        } else {
+            let mut sink = Sink::new(4);
+            sink.write(true);
+            Some(sink.bytes().to_vec())
+        }
+    });
+    let item = data_id_item();
+    assert!(dtoken_seller_publish(
+        b"resource_id",
+        &data_id_ddo(&manager).to_bytes(),
+        &item.to_bytes(),
+        b"test",
+        vec![],
+    ));
+    let template_bytes = item.templates[0].to_bytes();
+    assert_eq!(get_data_version(b"resource_id", &template_bytes), None);
+    assert!(pin_data_version(b"resource_id", &template_bytes, version));
+    assert_eq!(
+        get_data_version(b"resource_id", &template_bytes),
+        Some(version)
+    );
+    true
+}
+
+#[test]
+fn pin_data_version_published() {
+    assert!(pin_published_version(2));
+}
+
+#[test]
+#[should_panic(expected = "data version not published")]
+fn pin_data_version_unpublished() {
+    pin_published_version(3);
+}
+
+/// mock data id contract at address 6 owned by the seller 1, other contracts succeed
+fn mock_revocable_data_id(addr: &Address, data: &[u8], revoked: bool) -> Option<Vec<u8>> {
+    if addr == &Address::repeat_byte(6) {
+        mock_data_id_contract(data, &Address::repeat_byte(1), revoked, true)
+    } else {
+        let mut sink = Sink::new(4);
+        sink.write(true);
+        Some(sink.bytes().to_vec())
+    }
+}
+
+/// publish `data_id_item`, buyer 4 buys a token of it, then the data id is revoked
+/// and `action` runs with the signatures of buyers 4 and 5
+///
+/// the data id contract reports a frozen data id as revoked too
+fn after_revocation(check_on_use: bool, action: fn() -> bool) -> bool {
+    let manager = Address::repeat_byte(1);
+    let buyer = Address::repeat_byte(4);
+
+    let handle = build_runtime();
+    handle.witness(&[manager.clone(), ADMIN.clone()]);
+    assert!(set_data_id_contract(&Address::repeat_byte(6)));
+    handle.on_contract_call(|addr, data| mock_revocable_data_id(addr, data, false));
+    let mut item = data_id_item();
+    item.stocks = 2;
+    assert!(dtoken_seller_publish(
+        b"resource_id",
+        &data_id_ddo(&manager).to_bytes(),
+        &item.to_bytes(),
+        b"test",
+        vec![],
+    ));
+    assert!(set_check_revoked_on_use(b"resource_id", check_on_use));
+    handle.witness(&[buyer.clone()]);
+    assert!(buy_dtoken(b"resource_id", 1, &buyer));
+
+    handle.on_contract_call(|addr, data| mock_revocable_data_id(addr, data, true));
+    handle.witness(&[buyer.clone(), Address::repeat_byte(5)]);
+    action()
+}
+
+#[test]
+#[should_panic(expected = "data id is revoked")]
+fn buy_revoked_data_id() {
+    after_revocation(false, || {
+        buy_dtoken(b"resource_id", 1, &Address::repeat_byte(5))
+    });
+}
+
+#[test]
+#[should_panic(expected = "data id is revoked")]
+fn buy_revoked_data_id_from_reseller() {
+    after_revocation(false, || {
+        buy_dtoken_from_reseller(
+            b"resource_id",
+            1,
+            &Address::repeat_byte(5),
+            &Address::repeat_byte(4),
+        )
+    });
+}
+
+fn use_revoked_data_id() -> bool {
+    let template_bytes = data_id_item().templates[0].to_bytes();
+    use_token(b"resource_id", &Address::repeat_byte(4), &template_bytes, 1)
+}
+
+#[test]
+fn use_revoked_data_id_by_default() {
+    assert!(after_revocation(false, use_revoked_data_id));
+}
+
+#[test]
+#[should_panic(expected = "data id is revoked")]
+fn use_revoked_data_id_checked_on_use() {
+    after_revocation(true, use_revoked_data_id);
+}
+
+#[test]
+#[should_panic(expected = "data id contract not set")]
+fn publish_data_id_without_data_id_contract() {
+    let manager = Address::repeat_byte(1);
+    let handle = build_runtime();
+    handle.witness(&[manager.clone()]);
+    dtoken_seller_publish(
+        b"resource_id",
+        &data_id_ddo(&manager).to_bytes(),
+        &data_id_item().to_bytes(),
+        b"test",
+        vec![],
+    );
+}
+
 fn mock_mp_contract(
     _data: &[u8],
     ong_balance_map: &mut BTreeMap<Address, U128>,
@@ -1,16 +1,151 @@
-use super::{Address, Decoder, Encoder, Error, Sink, Source, U128};
+use super::{Address, Decoder, Encoder, Error, Sink, Source, Vec, MAX_PERCENTAGE, U128};
 use common::Fee;
 
-#[derive(Encoder, Decoder, Clone)]
+#[derive(Clone, PartialEq)]
+pub enum FeeSplitType {
+    Percentage,
+    Flat,
+    PercentageWithMin,
+    PercentageWithCap,
+}
+
+impl Encoder for FeeSplitType {
+    fn encode(&self, sink: &mut Sink) {
+        match self {
+            FeeSplitType::Percentage => {
+                sink.write(0u8);
+            }
+            FeeSplitType::Flat => {
+                sink.write(1u8);
+            }
+            FeeSplitType::PercentageWithMin => {
+                sink.write(2u8);
+            }
+            FeeSplitType::PercentageWithCap => {
+                sink.write(3u8);
+            }
+        }
+    }
+}
+
+impl<'a> Decoder<'a> for FeeSplitType {
+    fn decode(source: &mut Source<'a>) -> Result<Self, Error> {
+        let ty = source.read_byte()?;
+        match ty {
+            0 => Ok(FeeSplitType::Percentage),
+            1 => Ok(FeeSplitType::Flat),
+            2 => Ok(FeeSplitType::PercentageWithMin),
+            3 => Ok(FeeSplitType::PercentageWithCap),
+            _ => panic!("not support fee split type:{}", ty),
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct FeeSplitModel {
-    pub percentage: u16,
+    pub percentage: u16, // mp share of the order amount, unused by Flat
+    pub split_type: FeeSplitType,
+    pub amount: U128, // flat fee, minimum or cap of the mp share, depending on split_type
+    pub effective_from: u64, // timestamp from which orders are settled with this model
+}
+
+impl Encoder for FeeSplitModel {
+    fn encode(&self, sink: &mut Sink) {
+        sink.write(self.percentage);
+        self.split_type.encode(sink);
+        sink.write(self.amount);
+        sink.write(self.effective_from);
+    }
+}
+
+impl<'a> Decoder<'a> for FeeSplitModel {
+    fn decode(source: &mut Source<'a>) -> Result<Self, Error> {
+        let percentage = source.read()?;
+        // a model stored before the split types were added only has the percentage
+        let split_type = match source.read() {
+            Ok(split_type) => split_type,
+            Err(_) => {
+                return Ok(FeeSplitModel {
+                    percentage,
+                    ..FeeSplitModel::default()
+                })
+            }
+        };
+        Ok(FeeSplitModel {
+            percentage,
+            split_type,
+            amount: source.read()?,
+            effective_from: source.read()?,
+        })
+    }
+}
+
+impl FeeSplitModel {
+    pub fn default() -> Self {
+        FeeSplitModel {
+            percentage: 0,
+            split_type: FeeSplitType::Percentage,
+            amount: 0,
+            effective_from: 0,
+        }
+    }
+
+    /// the mp share of an order whose total amount is `total`, never more than `total`
+    pub fn mp_amount(&self, total: U128) -> U128 {
+        let percent_amt = total
+            .checked_mul(self.percentage as U128)
+            .unwrap()
+            .checked_div(MAX_PERCENTAGE)
+            .unwrap();
+        let amt = match self.split_type {
+            FeeSplitType::Percentage => percent_amt,
+            FeeSplitType::Flat => self.amount,
+            FeeSplitType::PercentageWithMin => percent_amt.max(self.amount),
+            FeeSplitType::PercentageWithCap => percent_amt.min(self.amount),
+        };
+        amt.min(total)
+    }
+}
+
+#[derive(Encoder, Decoder, Clone)]
+pub struct FeeSplitProposal {
+    pub id: u64,
+    pub proposer: Address, // seller or mp, the other one needs to accept the proposal
+    pub seller: Address,
+    pub resource_id: Option<Vec<u8>>, // None means a seller level model
+    pub model: FeeSplitModel,
+    pub expiry: u64,
 }
 
-#[derive(Encoder, Decoder)]
 pub struct SettleInfo {
     pub split_contract_addr: Address,
     pub fee: Fee,
     pub n: U128,
+    pub timestamp: Option<u64>, // order time, used to pick the fee split model in effect
+    pub seller: Option<Address>, // seller of the item, the only account which can settle the order
+}
+
+impl Encoder for SettleInfo {
+    fn encode(&self, sink: &mut Sink) {
+        sink.write(&self.split_contract_addr);
+        sink.write(&self.fee);
+        sink.write(self.n);
+        sink.write(&self.timestamp);
+        sink.write(&self.seller);
+    }
+}
+
+impl<'a> Decoder<'a> for SettleInfo {
+    fn decode(source: &mut Source<'a>) -> Result<Self, Error> {
+        Ok(SettleInfo {
+            split_contract_addr: source.read()?,
+            fee: source.read()?,
+            n: source.read()?,
+            // orders placed before the order time was recorded have none
+            timestamp: source.read().unwrap_or(None),
+            seller: source.read().unwrap_or(None),
+        })
+    }
 }
 
 impl SettleInfo {
@@ -19,6 +154,16 @@ impl SettleInfo {
             split_contract_addr: Address::new([0u8; 20]),
             fee: Fee::default(),
             n: 0,
+            timestamp: None,
+            seller: None,
         }
     }
 }
+
+#[derive(Encoder, Decoder, Clone)]
+pub struct Endorsement {
+    pub endorser: Address, // mp account which endorses the item
+    pub level: u8,
+    pub expiry: u64,
+    pub endorsed_at: u64,
+}
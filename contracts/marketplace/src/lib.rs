@@ -2,10 +2,10 @@
 #![feature(proc_macro_hygiene)]
 extern crate alloc;
 extern crate ontio_std as ostd;
-use ostd::abi::{Decoder, Encoder, Error, Sink, Source};
+use ostd::abi::{Decoder, Encoder, Error, EventBuilder, Sink, Source};
 use ostd::database;
 use ostd::prelude::*;
-use ostd::runtime::{address, check_witness, input, ret};
+use ostd::runtime::{address, check_witness, input, ret, timestamp};
 use ostd::types::{Address, U128};
 mod utils;
 use ostd::contract::{ong, ont, wasm};
@@ -13,7 +13,7 @@ use utils::*;
 mod basic;
 use basic::*;
 extern crate common;
-use common::{Fee, OrderId, TokenType};
+use common::{Currency, Fee, OrderId, TokenType};
 #[cfg(test)]
 mod test;
 
@@ -29,24 +29,236 @@ fn set_mp(mp_account: &Address) -> bool {
 ///
 /// `seller_acc` is seller address
 ///
-/// `fee_split_model` is the charging model that is agreed by the seller and MP
+/// `fee_split_model` is the charging model that is agreed by the seller and MP,
+/// it applies to all the items of the seller which have no item level model
 fn set_fee_split_model(seller_acc: &Address, fee_split_model: FeeSplitModel) -> bool {
-    assert!(fee_split_model.percentage <= MAX_PERCENTAGE as u16);
     let mp = get_mp_account();
     assert!(check_witness(seller_acc) && check_witness(&mp));
-    let mp = database::get::<_, Address>(KEY_MP).unwrap();
-    assert!(check_witness(&mp) && check_witness(&seller_acc));
-    database::put(
-        utils::generate_fee_split_model_key(seller_acc),
+    add_fee_split_model(seller_models_key(seller_acc), fee_split_model);
+    true
+}
+
+/// set charging model of one item, need mp and seller signature
+///
+/// `seller_acc` is seller address
+///
+/// `resource_id` used to mark the only commodity in the chain
+///
+/// `fee_split_model` is the charging model that is agreed by the seller and MP
+fn set_item_fee_split_model(
+    seller_acc: &Address,
+    resource_id: &[u8],
+    fee_split_model: FeeSplitModel,
+) -> bool {
+    let mp = get_mp_account();
+    assert!(check_witness(seller_acc) && check_witness(&mp));
+    add_fee_split_model(
+        utils::generate_item_fee_split_model_key(seller_acc, resource_id),
         fee_split_model,
     );
     true
 }
 
-/// query seller's charging model by seller's address
+/// propose a charging model, need proposer signature
+///
+/// the proposer is the seller or the mp, the other one accepts or rejects it in a separate transaction
+///
+/// `resource_id` is the item the model applies to, None means a seller level model
+///
+/// `expiry` is the timestamp after which the proposal can not be accepted
+fn propose_fee_split_model(
+    proposer: &Address,
+    seller_acc: &Address,
+    resource_id: Option<Vec<u8>>,
+    fee_split_model: FeeSplitModel,
+    expiry: u64,
+) -> bool {
+    assert!(check_witness(proposer));
+    assert!(proposer == seller_acc || proposer == &get_mp_account());
+    assert!(fee_split_model.percentage <= MAX_PERCENTAGE as u16);
+    let now = timestamp();
+    assert!(expiry > now);
+    let id = database::get::<_, u64>(utils::KEY_PROPOSAL_ID).unwrap_or(0);
+    database::put(utils::KEY_PROPOSAL_ID, id + 1);
+    let mut proposals = get_fee_split_proposals(seller_acc);
+    proposals.retain(|p| p.expiry > now);
+    proposals.push(FeeSplitProposal {
+        id,
+        proposer: proposer.clone(),
+        seller: seller_acc.clone(),
+        resource_id,
+        model: fee_split_model,
+        expiry,
+    });
+    database::put(
+        utils::generate_fee_split_proposal_key(seller_acc),
+        proposals,
+    );
+    EventBuilder::new()
+        .string("proposeFeeSplitModel")
+        .address(proposer)
+        .address(seller_acc)
+        .number(id as U128)
+        .notify();
+    true
+}
+
+/// accept a charging model proposal, need the signature of the counter-party of the proposer
+///
+/// `id` is the id of the proposal
+fn accept_fee_split_proposal(acceptor: &Address, seller_acc: &Address, id: u64) -> bool {
+    assert!(check_witness(acceptor));
+    let proposal = take_fee_split_proposal(seller_acc, id);
+    assert!(proposal.expiry > timestamp());
+    let mp = get_mp_account();
+    if proposal.proposer == proposal.seller {
+        assert!(acceptor == &mp);
+    } else {
+        assert!(proposal.proposer == mp);
+        assert!(acceptor == seller_acc);
+    }
+    let key = match proposal.resource_id.as_ref() {
+        Some(resource_id) => utils::generate_item_fee_split_model_key(seller_acc, resource_id),
+        None => seller_models_key(seller_acc),
+    };
+    add_fee_split_model(key, proposal.model);
+    EventBuilder::new()
+        .string("acceptFeeSplitProposal")
+        .address(acceptor)
+        .address(seller_acc)
+        .number(id as U128)
+        .notify();
+    true
+}
+
+/// reject a charging model proposal, need seller or mp signature
+///
+/// the proposer can also withdraw the proposal by this method
+fn reject_fee_split_proposal(account: &Address, seller_acc: &Address, id: u64) -> bool {
+    assert!(check_witness(account));
+    assert!(account == seller_acc || account == &get_mp_account());
+    take_fee_split_proposal(seller_acc, id);
+    EventBuilder::new()
+        .string("rejectFeeSplitProposal")
+        .address(account)
+        .address(seller_acc)
+        .number(id as U128)
+        .notify();
+    true
+}
+
+/// query the pending charging model proposals of the seller
+fn get_fee_split_proposals(seller_acc: &Address) -> Vec<FeeSplitProposal> {
+    database::get::<_, Vec<FeeSplitProposal>>(utils::generate_fee_split_proposal_key(seller_acc))
+        .unwrap_or(vec![])
+}
+
+fn take_fee_split_proposal(seller_acc: &Address, id: u64) -> FeeSplitProposal {
+    let mut proposals = get_fee_split_proposals(seller_acc);
+    let index = proposals
+        .iter()
+        .position(|p| p.id == id)
+        .expect("not found the proposal");
+    let proposal = proposals.remove(index);
+    let key = utils::generate_fee_split_proposal_key(seller_acc);
+    if proposals.is_empty() {
+        database::delete(key);
+    } else {
+        database::put(key, proposals);
+    }
+    proposal
+}
+
+/// query seller's charging model in effect by seller's address
 fn get_fee_split_model(seller_acc: &Address) -> FeeSplitModel {
-    database::get::<_, FeeSplitModel>(utils::generate_fee_split_model_key(seller_acc))
-        .unwrap_or(FeeSplitModel { percentage: 0 })
+    seller_fee_split_model_at(seller_acc, timestamp()).unwrap_or(FeeSplitModel::default())
+}
+
+/// query the charging model in effect for the item, fall back to the seller's charging model
+fn get_item_fee_split_model(seller_acc: &Address, resource_id: &[u8]) -> FeeSplitModel {
+    fee_split_model_at(seller_acc, resource_id, timestamp())
+}
+
+/// models are kept one per entry in the order of `effective_from`, a model which starts earlier
+/// than `now` starts from `now`, so that it never applies to orders which are placed before
+///
+/// models which start from the same time or later than the new one have never been in effect,
+/// they are replaced by the new one
+fn add_fee_split_model(key: Vec<u8>, mut fee_split_model: FeeSplitModel) {
+    assert!(fee_split_model.percentage <= MAX_PERCENTAGE as u16);
+    let now = timestamp();
+    if fee_split_model.effective_from < now {
+        fee_split_model.effective_from = now;
+    }
+    let mut count = get_fee_split_model_count(&key);
+    while count != 0
+        && get_fee_split_model_entry(&key, count - 1).effective_from
+            >= fee_split_model.effective_from
+    {
+        count -= 1;
+        database::delete(utils::generate_fee_split_model_entry_key(&key, count));
+    }
+    database::put(
+        utils::generate_fee_split_model_entry_key(&key, count),
+        fee_split_model,
+    );
+    database::put(utils::generate_fee_split_model_count_key(&key), count + 1);
+}
+
+fn get_fee_split_model_count(key: &[u8]) -> u32 {
+    database::get::<_, u32>(utils::generate_fee_split_model_count_key(key)).unwrap_or(0)
+}
+
+fn get_fee_split_model_entry(key: &[u8], index: u32) -> FeeSplitModel {
+    database::get::<_, FeeSplitModel>(utils::generate_fee_split_model_entry_key(key, index))
+        .unwrap()
+}
+
+/// the key of the seller level models, the single model stored before the model history was
+/// kept is moved into the history and starts from 0
+fn seller_models_key(seller_acc: &Address) -> Vec<u8> {
+    let key = utils::generate_fee_split_models_key(seller_acc);
+    let legacy_key = utils::generate_fee_split_model_key(seller_acc);
+    if let Some(mut legacy) = database::get::<_, FeeSplitModel>(&legacy_key) {
+        legacy.effective_from = 0;
+        database::put(utils::generate_fee_split_model_entry_key(&key, 0), legacy);
+        database::put(utils::generate_fee_split_model_count_key(&key), 1u32);
+        database::delete(legacy_key);
+    }
+    key
+}
+
+/// binary search the last model which starts no later than `at`
+fn effective_fee_split_model(key: &[u8], at: u64) -> Option<FeeSplitModel> {
+    let (mut low, mut high) = (0, get_fee_split_model_count(key));
+    while low < high {
+        let mid = low + (high - low) / 2;
+        if get_fee_split_model_entry(key, mid).effective_from <= at {
+            low = mid + 1;
+        } else {
+            high = mid;
+        }
+    }
+    if low == 0 {
+        None
+    } else {
+        Some(get_fee_split_model_entry(key, low - 1))
+    }
+}
+
+fn seller_fee_split_model_at(seller_acc: &Address, at: u64) -> Option<FeeSplitModel> {
+    effective_fee_split_model(&utils::generate_fee_split_models_key(seller_acc), at).or_else(|| {
+        database::get::<_, FeeSplitModel>(utils::generate_fee_split_model_key(seller_acc))
+    })
+}
+
+fn fee_split_model_at(seller_acc: &Address, resource_id: &[u8], at: u64) -> FeeSplitModel {
+    effective_fee_split_model(
+        &utils::generate_item_fee_split_model_key(seller_acc, resource_id),
+        at,
+    )
+    .or_else(|| seller_fee_split_model_at(seller_acc, at))
+    .unwrap_or(FeeSplitModel::default())
 }
 
 /// transfer fee to the contract and register the income distribution balance of this order
@@ -60,12 +272,16 @@ fn get_fee_split_model(seller_acc: &Address) -> FeeSplitModel {
 /// `fee` is the cost of one share
 ///
 /// `n` is the number of shares purchased
+///
+/// `seller_acc` is the seller of the item, only the seller can settle the order,
+/// it is None when the caller does not pass it
 fn transfer_amount(
     order_id_bytes: &[u8],
     buyer_acc: &Address,
     split_contract_address: &Address,
     fee: Fee,
     n: U128,
+    seller_acc: Option<Address>,
 ) -> bool {
     assert!(check_witness(buyer_acc));
     let amt = n.checked_mul(fee.count as U128).unwrap();
@@ -79,10 +295,13 @@ fn transfer_amount(
     ));
 
     //store information that split_contract needs
+    let now = timestamp();
     let info = SettleInfo {
         split_contract_addr: split_contract_address.clone(),
         fee,
         n,
+        timestamp: Some(now),
+        seller: seller_acc,
     };
     database::put(utils::generate_balance_key(order_id_bytes), info);
     true
@@ -94,9 +313,12 @@ fn get_settle_info(order_id: &[u8]) -> SettleInfo {
         .unwrap_or(SettleInfo::default())
 }
 
-/// expense settlement, first transfer fee to mp, second invoke "transferWithdraw" method of split contract
+/// expense settlement, first credit fee to mp earnings, second invoke "transferWithdrawCurrency" method of split contract
+///
+/// the mp fee stays in the contract until the mp invokes `withdraw_earnings`
 ///
-/// `seller_acc` is the seller address, need the address signature
+/// `seller_acc` is the seller address, need the address signature,
+/// it must be the seller recorded in the order
 ///
 /// `order_id` is the serialization result of OrderId
 fn settle(seller_acc: &Address, order_id: &[u8]) -> bool {
@@ -104,28 +326,29 @@ fn settle(seller_acc: &Address, order_id: &[u8]) -> bool {
     let self_addr = address();
     let mp = get_mp_account();
     let info = get_settle_info(order_id);
+    if let Some(seller) = info.seller.as_ref() {
+        assert!(seller == seller_acc, "not the seller of the order");
+    }
+    let oi = OrderId::from_bytes(order_id);
 
     //1. mp
-    let fee_split = get_fee_split_model(seller_acc);
+    // orders placed before the order time was recorded are settled with the model in effect now
+    let at = info.timestamp.unwrap_or_else(timestamp);
+    let fee_split = fee_split_model_at(seller_acc, &oi.item_id, at);
     let fee = info.fee;
     let total = info.n.checked_mul(fee.count as U128).unwrap();
-    let mp_fee = total.checked_mul(fee_split.percentage as U128).unwrap();
-    let mp_amt = mp_fee.checked_div(MAX_PERCENTAGE).unwrap();
+    let mp_amt = fee_split.mp_amount(total);
     if mp_amt != 0 {
-        assert!(transfer(
-            &self_addr,
-            &mp,
-            mp_amt,
-            &fee.contract_type,
-            Some(fee.contract_addr)
-        ));
+        credit_earnings(&mp, &fee.currency(), mp_amt);
     }
     //2.split
     let seller_amt = total.checked_sub(mp_amt).unwrap();
-    let oi = OrderId::from_bytes(order_id);
     let res = wasm::call_contract(
         &info.split_contract_addr,
-        ("transferWithdraw", (&self_addr, oi.item_id, seller_amt)),
+        (
+            "transferWithdrawCurrency",
+            (&self_addr, oi.item_id, fee.currency(), seller_amt),
+        ),
     );
     if let Some(rr) = res {
         let mut source = Source::new(rr.as_slice());
@@ -138,6 +361,110 @@ fn settle(seller_acc: &Address, order_id: &[u8]) -> bool {
     true
 }
 
+fn credit_earnings(account: &Address, currency: &Currency, amt: U128) {
+    let key = utils::generate_earnings_key(account, currency);
+    let earnings = database::get::<_, U128>(&key).unwrap_or(0);
+    database::put(key, earnings.checked_add(amt).unwrap());
+}
+
+/// query the earnings of the account which have not been withdrawn
+///
+/// `currency` is the token the earnings are paid in
+fn get_earnings(account: &Address, currency: &Currency) -> U128 {
+    database::get::<_, U128>(utils::generate_earnings_key(account, currency)).unwrap_or(0)
+}
+
+/// withdraw all the earnings of the account in the specified token, need account signature
+fn withdraw_earnings(account: &Address, currency: &Currency) -> bool {
+    assert!(check_witness(account));
+    let key = utils::generate_earnings_key(account, currency);
+    let amt = database::get::<_, U128>(&key).unwrap_or(0);
+    assert_ne!(amt, 0);
+    database::delete(key);
+    assert!(transfer(
+        &address(),
+        account,
+        amt,
+        &currency.contract_type,
+        Some(currency.contract_addr.clone())
+    ));
+    EventBuilder::new()
+        .string("withdrawEarnings")
+        .address(account)
+        .number(amt)
+        .notify();
+    true
+}
+
+/// endorse an item listed in the marketplace, need mp signature
+///
+/// endorsing the same item again replaces the former endorsement of the mp
+///
+/// `resource_id` used to mark the only commodity in the chain
+///
+/// `level` is the endorsement level given by the mp
+///
+/// `expiry` is the timestamp after which the endorsement is invalid
+fn endorse_item(resource_id: &[u8], level: u8, expiry: u64) -> bool {
+    let mp = get_mp_account();
+    assert!(check_witness(&mp));
+    let now = timestamp();
+    assert!(expiry > now);
+    let mut endorsements = get_endorsements(resource_id);
+    endorsements.retain(|e| e.endorser != mp);
+    endorsements.push(Endorsement {
+        endorser: mp.clone(),
+        level,
+        expiry,
+        endorsed_at: now,
+    });
+    database::put(utils::generate_endorsement_key(resource_id), endorsements);
+    EventBuilder::new()
+        .string("endorseItem")
+        .bytearray(resource_id)
+        .address(&mp)
+        .number(level as U128)
+        .number(expiry as U128)
+        .notify();
+    true
+}
+
+/// revoke the endorsement of the mp, need mp signature
+fn revoke_endorsement(resource_id: &[u8]) -> bool {
+    let mp = get_mp_account();
+    assert!(check_witness(&mp));
+    let mut endorsements = get_endorsements(resource_id);
+    let l = endorsements.len();
+    endorsements.retain(|e| e.endorser != mp);
+    assert_ne!(l, endorsements.len());
+    if endorsements.is_empty() {
+        database::delete(utils::generate_endorsement_key(resource_id));
+    } else {
+        database::put(utils::generate_endorsement_key(resource_id), endorsements);
+    }
+    EventBuilder::new()
+        .string("revokeEndorsement")
+        .bytearray(resource_id)
+        .address(&mp)
+        .notify();
+    true
+}
+
+/// query all the endorsements of the item, including the expired ones
+fn get_endorsements(resource_id: &[u8]) -> Vec<Endorsement> {
+    database::get::<_, Vec<Endorsement>>(utils::generate_endorsement_key(resource_id))
+        .unwrap_or(vec![])
+}
+
+/// check whether the current mp has an unexpired endorsement on the item
+fn is_endorsed(resource_id: &[u8]) -> bool {
+    let mp = get_mp_account();
+    let now = timestamp();
+    get_endorsements(resource_id)
+        .iter()
+        .any(|e| e.endorser == mp && e.expiry > now)
+}
+
 fn transfer(
     from: &Address,
     to: &Address,
@@ -184,14 +511,53 @@ pub fn invoke() {
             let seller_acc = source.read().unwrap();
             sink.write(get_fee_split_model(seller_acc));
         }
+        b"proposeFeeSplitModel" => {
+            let (proposer, seller_acc, resource_id, fee_split_model, expiry) =
+                source.read().unwrap();
+            sink.write(propose_fee_split_model(
+                proposer,
+                seller_acc,
+                resource_id,
+                fee_split_model,
+                expiry,
+            ));
+        }
+        b"acceptFeeSplitProposal" => {
+            let (acceptor, seller_acc, id) = source.read().unwrap();
+            sink.write(accept_fee_split_proposal(acceptor, seller_acc, id));
+        }
+        b"rejectFeeSplitProposal" => {
+            let (account, seller_acc, id) = source.read().unwrap();
+            sink.write(reject_fee_split_proposal(account, seller_acc, id));
+        }
+        b"getFeeSplitProposals" => {
+            let seller_acc = source.read().unwrap();
+            sink.write(get_fee_split_proposals(seller_acc));
+        }
+        b"setItemFeeSplitModel" => {
+            let (seller_acc, resource_id, fee_split_model) = source.read().unwrap();
+            sink.write(set_item_fee_split_model(
+                seller_acc,
+                resource_id,
+                fee_split_model,
+            ));
+        }
+        b"get_item_fee_split_model" => {
+            let (seller_acc, resource_id) = source.read().unwrap();
+            sink.write(get_item_fee_split_model(seller_acc, resource_id));
+        }
         b"transferAmount" => {
-            let (order_id_bytes, buyer_acc, seller_acc, fee, n) = source.read().unwrap();
+            let (order_id_bytes, buyer_acc, split_contract_address, fee, n) =
+                source.read().unwrap();
+            // the seller is an optional trailing argument
+            let seller_acc = source.read().ok();
             sink.write(transfer_amount(
                 order_id_bytes,
                 buyer_acc,
-                seller_acc,
+                split_contract_address,
                 fee,
                 n,
+                seller_acc,
             ));
         }
         b"balance_of" => {
@@ -209,6 +575,30 @@ pub fn invoke() {
         b"get_mp_account" => {
             sink.write(get_mp_account());
         }
+        b"getEarnings" => {
+            let (account, currency) = source.read().unwrap();
+            sink.write(get_earnings(account, &currency));
+        }
+        b"withdrawEarnings" => {
+            let (account, currency) = source.read().unwrap();
+            sink.write(withdraw_earnings(account, &currency));
+        }
+        b"endorseItem" => {
+            let (resource_id, level, expiry) = source.read().unwrap();
+            sink.write(endorse_item(resource_id, level, expiry));
+        }
+        b"revokeEndorsement" => {
+            let resource_id = source.read().unwrap();
+            sink.write(revoke_endorsement(resource_id));
+        }
+        b"getEndorsements" => {
+            let resource_id = source.read().unwrap();
+            sink.write(get_endorsements(resource_id));
+        }
+        b"isEndorsed" => {
+            let resource_id = source.read().unwrap();
+            sink.write(is_endorsed(resource_id));
+        }
         _ => {
             let method = str::from_utf8(action).ok().unwrap();
             panic!("not support method:{}", method)
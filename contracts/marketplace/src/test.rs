@@ -17,7 +17,7 @@ fn test() {
     assert_eq!(get_mp_account(), mp);
 
     let seller = Address::repeat_byte(2);
-    let fee_split = FeeSplitModel { percentage: 10 };
+    let fee_split = percentage_model(10, 0);
     build.witness(&[seller.clone(), mp.clone()]);
     assert!(set_fee_split_model(&seller, fee_split.clone()));
     let fee_split2 = get_fee_split_model(&seller);
@@ -56,7 +56,8 @@ fn test() {
         &buyer,
         &seller,
         fee,
-        1
+        1,
+        Some(seller.clone())
     ));
 
     let seller_balance = get_settle_info(oi.to_bytes().as_slice());
@@ -67,6 +68,386 @@ fn test() {
     let seller_balance = get_settle_info(oi.to_bytes().as_slice());
 }
 
+#[test]
+fn earnings_test() {
+    let build = build_runtime();
+    build.witness(&[ADMIN]);
+    let mp = Address::repeat_byte(1);
+    assert!(set_mp(&mp));
+
+    let seller = Address::repeat_byte(2);
+    build.witness(&[seller.clone(), mp.clone()]);
+    assert!(set_fee_split_model(&seller, percentage_model(1000, 0)));
+
+    let buyer = Address::repeat_byte(3);
+    let fee = Fee {
+        contract_addr: ONG_CONTRACT_ADDRESS,
+        contract_type: TokenType::ONG,
+        count: 100,
+    };
+    let mut ong_balance_map: BTreeMap<Address, U128> = BTreeMap::new();
+    ong_balance_map.insert(buyer.clone(), 10000);
+    build.on_contract_call(move |_addr: &Address, _data: &[u8]| -> Option<Vec<u8>> {
+        if _addr == &ONG_CONTRACT_ADDRESS {
+            mock_ong_contract(_data, &mut ong_balance_map)
+        } else {
+            Some(vec![1u8])
+        }
+    });
+    let self_addr = Address::repeat_byte(4);
+    build.address(&self_addr);
+
+    let oi = OrderId {
+        item_id: vec![0u8, 1u8],
+        tx_hash: H256::new([0u8; 32]),
+    };
+    build.witness(&[buyer.clone()]);
+    assert!(transfer_amount(
+        oi.to_bytes().as_slice(),
+        &buyer,
+        &seller,
+        fee.clone(),
+        10,
+        Some(seller.clone())
+    ));
+
+    build.witness(&[seller.clone()]);
+    assert!(settle(&seller, oi.to_bytes().as_slice()));
+    assert_eq!(get_earnings(&mp, &fee.currency()), 100);
+
+    build.witness(&[mp.clone()]);
+    assert!(withdraw_earnings(&mp, &fee.currency()));
+    assert_eq!(get_earnings(&mp, &fee.currency()), 0);
+}
+
+#[test]
+fn item_fee_split_model_test() {
+    let build = build_runtime();
+    build.witness(&[ADMIN]);
+    let mp = Address::repeat_byte(1);
+    assert!(set_mp(&mp));
+
+    let seller = Address::repeat_byte(2);
+    let resource_id = b"resource_id";
+    build.witness(&[seller.clone(), mp.clone()]);
+    assert!(set_fee_split_model(&seller, percentage_model(1000, 0)));
+    assert_eq!(
+        get_item_fee_split_model(&seller, resource_id).percentage,
+        1000
+    );
+
+    let capped = FeeSplitModel {
+        split_type: FeeSplitType::PercentageWithCap,
+        percentage: 2000,
+        amount: 50,
+        effective_from: 100,
+    };
+    assert!(set_item_fee_split_model(&seller, resource_id, capped));
+    // the new model is not in effect yet, fall back to the seller level model
+    assert_eq!(fee_split_model_at(&seller, resource_id, 99).mp_amount(1000), 100);
+    assert_eq!(fee_split_model_at(&seller, resource_id, 100).mp_amount(1000), 50);
+    assert_eq!(fee_split_model_at(&seller, resource_id, 100).mp_amount(100), 20);
+    assert_eq!(fee_split_model_at(&seller, b"other", 100).mp_amount(1000), 100);
+}
+
+#[test]
+fn fee_split_proposal_test() {
+    let build = build_runtime();
+    build.witness(&[ADMIN]);
+    let mp = Address::repeat_byte(1);
+    assert!(set_mp(&mp));
+    let seller = Address::repeat_byte(2);
+    let resource_id = b"resource_id".to_vec();
+
+    build.witness(&[seller.clone()]);
+    assert!(propose_fee_split_model(
+        &seller,
+        &seller,
+        Some(resource_id.clone()),
+        percentage_model(500, 0),
+        100
+    ));
+    let proposals = get_fee_split_proposals(&seller);
+    assert_eq!(proposals.len(), 1);
+
+    build.witness(&[mp.clone()]);
+    assert!(accept_fee_split_proposal(&mp, &seller, proposals[0].id));
+    assert!(get_fee_split_proposals(&seller).is_empty());
+    assert_eq!(
+        get_item_fee_split_model(&seller, &resource_id).percentage,
+        500
+    );
+
+    assert!(propose_fee_split_model(
+        &mp,
+        &seller,
+        None,
+        percentage_model(800, 0),
+        100
+    ));
+    let id = get_fee_split_proposals(&seller)[0].id;
+    build.witness(&[seller.clone()]);
+    assert!(reject_fee_split_proposal(&seller, &seller, id));
+    assert!(get_fee_split_proposals(&seller).is_empty());
+    assert_eq!(get_fee_split_model(&seller).percentage, 0);
+}
+
+#[test]
+#[should_panic]
+fn fee_split_proposal_accepted_by_proposer() {
+    let build = build_runtime();
+    build.witness(&[ADMIN]);
+    let mp = Address::repeat_byte(1);
+    assert!(set_mp(&mp));
+    let seller = Address::repeat_byte(2);
+
+    build.witness(&[seller.clone()]);
+    assert!(propose_fee_split_model(
+        &seller,
+        &seller,
+        None,
+        percentage_model(500, 0),
+        100
+    ));
+    let id = get_fee_split_proposals(&seller)[0].id;
+    accept_fee_split_proposal(&seller, &seller, id);
+}
+
+#[test]
+fn fee_split_model_amount() {
+    let flat = FeeSplitModel {
+        split_type: FeeSplitType::Flat,
+        percentage: 0,
+        amount: 30,
+        effective_from: 0,
+    };
+    assert_eq!(flat.mp_amount(1000), 30);
+    assert_eq!(flat.mp_amount(10), 10);
+    let min = FeeSplitModel {
+        split_type: FeeSplitType::PercentageWithMin,
+        percentage: 1000,
+        amount: 30,
+        effective_from: 0,
+    };
+    assert_eq!(min.mp_amount(1000), 100);
+    assert_eq!(min.mp_amount(100), 30);
+}
+
+#[test]
+fn settle_with_native_currency_alias() {
+    let build = build_runtime();
+    build.witness(&[ADMIN]);
+    let mp = Address::repeat_byte(1);
+    assert!(set_mp(&mp));
+    let seller = Address::repeat_byte(2);
+    let buyer = Address::repeat_byte(3);
+    let split_contract = Address::repeat_byte(5);
+    let mut ong_balance_map: BTreeMap<Address, U128> = BTreeMap::new();
+    ong_balance_map.insert(buyer.clone(), 10000);
+    build.on_contract_call(move |addr: &Address, data: &[u8]| -> Option<Vec<u8>> {
+        if addr == &ONG_CONTRACT_ADDRESS {
+            return mock_ong_contract(data, &mut ong_balance_map);
+        }
+        // the split policy accepts ONG registered with the zero address
+        assert_eq!(addr, &split_contract);
+        let mut source = Source::new(data);
+        let (method, (_, _, currency, _)): (&[u8], (Address, &[u8], Currency, U128)) =
+            source.read().unwrap();
+        assert_eq!(method, b"transferWithdrawCurrency");
+        let registered = Currency {
+            contract_addr: Address::new([0u8; 20]),
+            contract_type: TokenType::ONG,
+        };
+        Some(vec![(currency == registered) as u8])
+    });
+    build.address(&Address::repeat_byte(4));
+
+    let fee = Fee {
+        contract_addr: ONG_CONTRACT_ADDRESS,
+        contract_type: TokenType::ONG,
+        count: 100,
+    };
+    let oi = OrderId {
+        item_id: vec![0u8, 1u8],
+        tx_hash: H256::new([0u8; 32]),
+    };
+    build.witness(&[buyer.clone()]);
+    assert!(transfer_amount(
+        oi.to_bytes().as_slice(),
+        &buyer,
+        &split_contract,
+        fee,
+        1,
+        Some(seller.clone())
+    ));
+    build.witness(&[seller.clone()]);
+    assert!(settle(&seller, oi.to_bytes().as_slice()));
+}
+
+#[test]
+fn legacy_records_test() {
+    let build = build_runtime();
+    build.witness(&[ADMIN]);
+    let mp = Address::repeat_byte(1);
+    assert!(set_mp(&mp));
+    build.on_contract_call(|_addr: &Address, _data: &[u8]| -> Option<Vec<u8>> { Some(vec![1u8]) });
+
+    // a model and an order stored before the model history and the order time were kept
+    let seller = Address::repeat_byte(2);
+    database::put(utils::generate_fee_split_model_key(&seller), 1000u16);
+    let fee = Fee {
+        contract_addr: ONG_CONTRACT_ADDRESS,
+        contract_type: TokenType::ONG,
+        count: 100,
+    };
+    let oi = OrderId {
+        item_id: vec![0u8, 1u8],
+        tx_hash: H256::new([0u8; 32]),
+    };
+    database::put(
+        utils::generate_balance_key(&oi.to_bytes()),
+        (&seller, &fee, 10 as U128),
+    );
+    assert_eq!(get_fee_split_model(&seller).percentage, 1000);
+
+    build.timestamp(50);
+    build.witness(&[seller.clone()]);
+    assert!(settle(&seller, oi.to_bytes().as_slice()));
+    assert_eq!(get_earnings(&mp, &fee.currency()), 100);
+
+    build.witness(&[seller.clone(), mp.clone()]);
+    assert!(set_fee_split_model(&seller, percentage_model(500, 60)));
+    assert!(
+        database::get::<_, FeeSplitModel>(utils::generate_fee_split_model_key(&seller)).is_none()
+    );
+    assert_eq!(get_fee_split_model(&seller).percentage, 1000);
+    assert_eq!(fee_split_model_at(&seller, b"item", 60).percentage, 500);
+}
+
+#[test]
+fn fee_split_model_history() {
+    let build = build_runtime();
+    build.witness(&[ADMIN]);
+    let mp = Address::repeat_byte(1);
+    assert!(set_mp(&mp));
+    let seller = Address::repeat_byte(2);
+    let buyer = Address::repeat_byte(3);
+    let mut ong_balance_map: BTreeMap<Address, U128> = BTreeMap::new();
+    ong_balance_map.insert(buyer.clone(), 10000);
+    build.on_contract_call(move |_addr: &Address, _data: &[u8]| -> Option<Vec<u8>> {
+        if _addr == &ONG_CONTRACT_ADDRESS {
+            mock_ong_contract(_data, &mut ong_balance_map)
+        } else {
+            Some(vec![1u8])
+        }
+    });
+    build.address(&Address::repeat_byte(4));
+    let history = |seller: &Address| {
+        let key = utils::generate_fee_split_models_key(seller);
+        (0..get_fee_split_model_count(&key))
+            .map(|i| get_fee_split_model_entry(&key, i).percentage)
+            .collect::<Vec<u16>>()
+    };
+
+    build.timestamp(10);
+    build.witness(&[seller.clone(), mp.clone()]);
+    assert!(set_fee_split_model(&seller, percentage_model(100, 0)));
+    build.timestamp(20);
+    assert!(set_fee_split_model(&seller, percentage_model(200, 0)));
+    assert_eq!(history(&seller), vec![100, 200]);
+
+    build.timestamp(25);
+    let oi = OrderId {
+        item_id: vec![0u8, 1u8],
+        tx_hash: H256::new([0u8; 32]),
+    };
+    let fee = Fee {
+        contract_addr: ONG_CONTRACT_ADDRESS,
+        contract_type: TokenType::ONG,
+        count: 100,
+    };
+    build.witness(&[buyer.clone()]);
+    assert!(transfer_amount(
+        oi.to_bytes().as_slice(),
+        &buyer,
+        &seller,
+        fee.clone(),
+        10,
+        Some(seller.clone())
+    ));
+
+    // the order is settled with the model in effect when it was placed
+    build.timestamp(30);
+    build.witness(&[seller.clone(), mp.clone()]);
+    assert!(set_fee_split_model(&seller, percentage_model(300, 0)));
+    assert_eq!(history(&seller), vec![100, 200, 300]);
+    assert!(settle(&seller, oi.to_bytes().as_slice()));
+    assert_eq!(get_earnings(&mp, &fee.currency()), 20);
+
+    // a model which is not in effect yet is replaced by a model starting earlier
+    build.timestamp(40);
+    assert!(set_fee_split_model(&seller, percentage_model(400, 100)));
+    assert!(set_fee_split_model(&seller, percentage_model(500, 50)));
+    assert_eq!(history(&seller), vec![100, 200, 300, 500]);
+    let percentage_at = |at| seller_fee_split_model_at(&seller, at).map(|m| m.percentage);
+    assert_eq!(percentage_at(5), None);
+    assert_eq!(percentage_at(15), Some(100));
+    assert_eq!(percentage_at(49), Some(300));
+    assert_eq!(percentage_at(100), Some(500));
+}
+
+#[test]
+#[should_panic(expected = "not the seller of the order")]
+fn settle_by_others() {
+    let build = build_runtime();
+    build.witness(&[ADMIN]);
+    let mp = Address::repeat_byte(1);
+    assert!(set_mp(&mp));
+    let seller = Address::repeat_byte(2);
+    let buyer = Address::repeat_byte(3);
+    let mallory = Address::repeat_byte(5);
+    let mut ong_balance_map: BTreeMap<Address, U128> = BTreeMap::new();
+    ong_balance_map.insert(buyer.clone(), 10000);
+    build.on_contract_call(move |_addr: &Address, _data: &[u8]| -> Option<Vec<u8>> {
+        if _addr == &ONG_CONTRACT_ADDRESS {
+            mock_ong_contract(_data, &mut ong_balance_map)
+        } else {
+            Some(vec![1u8])
+        }
+    });
+    build.address(&Address::repeat_byte(4));
+    let oi = OrderId {
+        item_id: vec![0u8, 1u8],
+        tx_hash: H256::new([0u8; 32]),
+    };
+    let fee = Fee {
+        contract_addr: ONG_CONTRACT_ADDRESS,
+        contract_type: TokenType::ONG,
+        count: 100,
+    };
+    build.witness(&[buyer.clone()]);
+    assert!(transfer_amount(
+        oi.to_bytes().as_slice(),
+        &buyer,
+        &seller,
+        fee,
+        10,
+        Some(seller.clone())
+    ));
+
+    build.witness(&[mallory.clone()]);
+    settle(&mallory, oi.to_bytes().as_slice());
+}
+
+fn percentage_model(percentage: u16, effective_from: u64) -> FeeSplitModel {
+    FeeSplitModel {
+        split_type: FeeSplitType::Percentage,
+        percentage,
+        amount: 0,
+        effective_from,
+    }
+}
+
 fn mock_ong_contract(
     _data: &[u8],
     ong_balance_map: &mut BTreeMap<Address, U128>,
@@ -95,3 +476,29 @@ fn mock_ong_contract(
     }
     return Some(sink.bytes().to_vec());
 }
+
+#[test]
+fn endorse_test() {
+    let build = build_runtime();
+    build.witness(&[ADMIN]);
+    let mp = Address::repeat_byte(1);
+    assert!(set_mp(&mp));
+
+    let resource_id = b"resource_id";
+    assert!(!is_endorsed(resource_id));
+
+    build.witness(&[mp.clone()]);
+    build.timestamp(10);
+    assert!(endorse_item(resource_id, 1, 100));
+    assert!(is_endorsed(resource_id));
+    assert!(endorse_item(resource_id, 2, 100));
+    let endorsements = get_endorsements(resource_id);
+    assert_eq!(endorsements.len(), 1);
+    assert_eq!(endorsements[0].level, 2);
+
+    build.timestamp(100);
+    assert!(!is_endorsed(resource_id));
+
+    assert!(revoke_endorsement(resource_id));
+    assert!(get_endorsements(resource_id).is_empty());
+}
@@ -4,10 +4,38 @@ const KEY_FEE_SPLIT_MODEL: &[u8] = b"01";
 const KEY_BALANCE: &[u8] = b"02";
 pub const KEY_MP: &[u8] = b"03";
 const KEY_RESOURCE_ID: &[u8] = b"04";
+const KEY_ENDORSEMENT: &[u8] = b"05";
+const KEY_EARNINGS: &[u8] = b"06";
+const KEY_ITEM_FEE_SPLIT_MODEL: &[u8] = b"07";
+const KEY_FEE_SPLIT_PROPOSAL: &[u8] = b"08";
+pub const KEY_PROPOSAL_ID: &[u8] = b"09";
+const KEY_FEE_SPLIT_MODELS: &[u8] = b"10";
+const KEY_FEE_SPLIT_MODEL_COUNT: &[u8] = b"11";
 
 pub fn generate_fee_split_model_key(account: &Address) -> Vec<u8> {
     [KEY_FEE_SPLIT_MODEL, account.as_ref()].concat()
 }
+pub fn generate_fee_split_models_key(account: &Address) -> Vec<u8> {
+    [KEY_FEE_SPLIT_MODELS, account.as_ref()].concat()
+}
+pub fn generate_item_fee_split_model_key(account: &Address, resource_id: &[u8]) -> Vec<u8> {
+    [
+        KEY_ITEM_FEE_SPLIT_MODEL,
+        account.as_ref(),
+        (resource_id.len() as u32).to_le_bytes().as_ref(),
+        resource_id,
+    ]
+    .concat()
+}
+pub fn generate_fee_split_model_entry_key(models_key: &[u8], index: u32) -> Vec<u8> {
+    [models_key, index.to_le_bytes().as_ref()].concat()
+}
+pub fn generate_fee_split_model_count_key(models_key: &[u8]) -> Vec<u8> {
+    [KEY_FEE_SPLIT_MODEL_COUNT, models_key].concat()
+}
+pub fn generate_fee_split_proposal_key(seller: &Address) -> Vec<u8> {
+    [KEY_FEE_SPLIT_PROPOSAL, seller.as_ref()].concat()
+}
 pub fn generate_balance_key(order_id: &[u8]) -> Vec<u8> {
     [KEY_BALANCE, order_id].concat()
 }
@@ -15,3 +43,16 @@ pub fn generate_balance_key(order_id: &[u8]) -> Vec<u8> {
 pub fn generate_resource_id_key(addr: &Address) -> Vec<u8> {
     [KEY_RESOURCE_ID, addr.as_ref()].concat()
 }
+
+pub fn generate_endorsement_key(resource_id: &[u8]) -> Vec<u8> {
+    [KEY_ENDORSEMENT, resource_id].concat()
+}
+
+pub fn generate_earnings_key(account: &Address, currency: &Currency) -> Vec<u8> {
+    [
+        KEY_EARNINGS,
+        account.as_ref(),
+        currency.to_key_bytes().as_slice(),
+    ]
+    .concat()
+}
@@ -0,0 +1,69 @@
+use super::ostd::abi::{Decoder, Encoder, Error, Sink, Source};
+use super::ostd::prelude::*;
+use super::ostd::types::{Address, H256, U128};
+
+#[derive(Clone, PartialEq)]
+pub enum MpStatus {
+    Pending,
+    Active,
+    Suspended,
+}
+
+impl Encoder for MpStatus {
+    fn encode(&self, sink: &mut Sink) {
+        match self {
+            MpStatus::Pending => {
+                sink.write(0u8);
+            }
+            MpStatus::Active => {
+                sink.write(1u8);
+            }
+            MpStatus::Suspended => {
+                sink.write(2u8);
+            }
+        }
+    }
+}
+
+impl<'a> Decoder<'a> for MpStatus {
+    fn decode(source: &mut Source<'a>) -> Result<Self, Error> {
+        let ty = source.read_byte()?;
+        match ty {
+            0 => Ok(MpStatus::Pending),
+            1 => Ok(MpStatus::Active),
+            2 => Ok(MpStatus::Suspended),
+            _ => panic!("not support mp status:{}", ty),
+        }
+    }
+}
+
+/// metadata submitted by the marketplace operator at registration
+#[derive(Clone, Encoder, Decoder)]
+pub struct MarketplaceMeta {
+    pub name: Vec<u8>,
+    pub endpoint_hash: H256, // sha256 of the marketplace endpoint url
+    pub operator: Address,   // the account which operates the marketplace
+    pub fee_policy: Vec<u8>, // short summary of the fee policy
+}
+
+impl MarketplaceMeta {
+    pub fn from_bytes(data: &[u8]) -> Self {
+        let mut source = Source::new(data);
+        source.read().unwrap()
+    }
+    #[cfg(test)]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut sink = Sink::new(64);
+        sink.write(self);
+        sink.bytes().to_vec()
+    }
+}
+
+#[derive(Clone, Encoder, Decoder)]
+pub struct MarketplaceInfo {
+    pub mp_contract: Address,
+    pub meta: MarketplaceMeta,
+    pub stake: U128, // ONG staked by the operator, refunded on deregistration
+    pub status: MpStatus,
+    pub registered_at: u64,
+}
@@ -0,0 +1,342 @@
+//! registry contract
+//!
+//! Registry is the central place for Marketplace, each Marketplace registers itself here.
+//!
+//! A marketplace becomes active either by staking at least the minimum ONG stake,
+//! or by admin approval. Admin can suspend and resume a marketplace,
+//! and the operator or admin can deregister it, which refunds the stake.
+//! Only admin can deregister a suspended marketplace, and its stake goes to admin.
+//!
+//! The marketplace contract itself must authorize its registration, a suspended
+//! marketplace or operator can not register again until admin lifts the suspension.
+
+#![cfg_attr(not(feature = "mock"), no_std)]
+#![feature(proc_macro_hygiene)]
+extern crate alloc;
+extern crate ontio_std as ostd;
+use ostd::abi::{EventBuilder, Sink, Source};
+use ostd::contract::ong;
+use ostd::database;
+use ostd::prelude::*;
+use ostd::runtime::{address, caller, check_witness, input, ret, timestamp};
+use ostd::types::{Address, U128};
+mod basic;
+use basic::*;
+
+#[cfg(test)]
+mod test;
+
+const KEY_ADMIN: &[u8] = b"01";
+const KEY_MARKETPLACE: &[u8] = b"02";
+const KEY_MARKETPLACE_LIST: &[u8] = b"03";
+const KEY_MIN_STAKE: &[u8] = b"04";
+const KEY_SUSPENDED: &[u8] = b"05";
+const KEY_MARKETPLACE_COUNT: &[u8] = b"06";
+const KEY_MARKETPLACE_POSITION: &[u8] = b"07";
+
+const MAX_PAGE_SIZE: u32 = 100;
+
+const ADMIN: Address = ostd::macros::base58!("AYnhakv7kC9R5ppw65JoE2rt6xDzCjCTvD");
+
+/// need old admin signature
+///
+/// update the admin address, admin has the right to approve, suspend and deregister marketplaces
+fn update_admin(new_admin: &Address) -> bool {
+    let old_admin = get_admin();
+    assert!(check_witness(&old_admin));
+    database::put(KEY_ADMIN, new_admin);
+    true
+}
+
+/// query admin address
+fn get_admin() -> Address {
+    database::get::<_, Address>(KEY_ADMIN).unwrap_or(ADMIN)
+}
+
+/// set the minimum ONG stake which activates a marketplace without admin approval, need admin signature
+///
+/// 0 means staking is disabled and every marketplace needs admin approval
+fn set_min_stake(amount: U128) -> bool {
+    assert!(check_witness(&get_admin()));
+    database::put(KEY_MIN_STAKE, amount);
+    true
+}
+
+/// query the minimum ONG stake
+fn get_min_stake() -> U128 {
+    database::get::<_, U128>(KEY_MIN_STAKE).unwrap_or(0)
+}
+
+/// register marketplace, need operator signature
+///
+/// `mp_contract` is the marketplace contract address, the marketplace contract must invoke
+/// this method itself or sign as a witness
+///
+/// `meta_bytes` is the serialization result of MarketplaceMeta
+///
+/// `stake` is the amount of ONG the operator stakes, the marketplace is active at once
+/// if the stake reaches the minimum stake, otherwise it waits for admin approval
+pub fn register_marketplace(mp_contract: &Address, meta_bytes: &[u8], stake: U128) -> bool {
+    let meta = MarketplaceMeta::from_bytes(meta_bytes);
+    assert!(check_witness(&meta.operator));
+    assert!(
+        &caller() == mp_contract || check_witness(mp_contract),
+        "marketplace contract did not authorize the registration"
+    );
+    assert!(get_marketplace(mp_contract).is_none());
+    assert!(
+        !is_suspended(mp_contract) && !is_suspended(&meta.operator),
+        "marketplace is suspended"
+    );
+    if stake != 0 {
+        assert!(ong::transfer(&meta.operator, &address(), stake));
+    }
+    let min_stake = get_min_stake();
+    let status = if min_stake != 0 && stake >= min_stake {
+        MpStatus::Active
+    } else {
+        MpStatus::Pending
+    };
+    let info = MarketplaceInfo {
+        mp_contract: mp_contract.clone(),
+        meta,
+        stake,
+        status,
+        registered_at: timestamp(),
+    };
+    database::put(utils::generate_marketplace_key(mp_contract), &info);
+    let count = get_marketplace_count();
+    database::put(utils::generate_marketplace_list_key(count), mp_contract);
+    database::put(utils::generate_marketplace_position_key(mp_contract), count);
+    database::put(KEY_MARKETPLACE_COUNT, count + 1);
+    EventBuilder::new()
+        .string("registerMarketplace")
+        .address(mp_contract)
+        .address(&info.meta.operator)
+        .number(stake)
+        .notify();
+    true
+}
+
+/// approve a pending marketplace, need admin signature
+pub fn approve_marketplace(mp_contract: &Address) -> bool {
+    update_status(mp_contract, MpStatus::Pending, MpStatus::Active, "approveMarketplace")
+}
+
+/// suspend an active marketplace, need admin signature
+pub fn suspend_marketplace(mp_contract: &Address) -> bool {
+    update_status(
+        mp_contract,
+        MpStatus::Active,
+        MpStatus::Suspended,
+        "suspendMarketplace",
+    )
+}
+
+/// resume a suspended marketplace, need admin signature
+pub fn resume_marketplace(mp_contract: &Address) -> bool {
+    update_status(
+        mp_contract,
+        MpStatus::Suspended,
+        MpStatus::Active,
+        "resumeMarketplace",
+    )
+}
+
+/// deregister marketplace and refund the stake to the operator
+///
+/// need operator or admin signature, a suspended marketplace can only be deregistered by admin,
+/// its stake goes to admin instead of the operator, and the marketplace and its operator
+/// can not register again until admin lifts the suspension
+pub fn deregister_marketplace(mp_contract: &Address) -> bool {
+    let info = get_marketplace(mp_contract).expect("marketplace not registered");
+    let admin = get_admin();
+    let suspended = info.status == MpStatus::Suspended;
+    let receiver = if suspended {
+        assert!(check_witness(&admin), "marketplace is suspended");
+        database::put(utils::generate_suspended_key(mp_contract), true);
+        database::put(utils::generate_suspended_key(&info.meta.operator), true);
+        admin
+    } else {
+        assert!(check_witness(&info.meta.operator) || check_witness(&admin));
+        info.meta.operator.clone()
+    };
+    if info.stake != 0 {
+        assert!(ong::transfer(&address(), &receiver, info.stake));
+    }
+    database::delete(utils::generate_marketplace_key(mp_contract));
+    // the entry is left empty, so that the cursors after it do not move
+    let position_key = utils::generate_marketplace_position_key(mp_contract);
+    if let Some(position) = database::get::<_, u32>(&position_key) {
+        database::delete(utils::generate_marketplace_list_key(position));
+        database::delete(position_key);
+    }
+    EventBuilder::new()
+        .string("deregisterMarketplace")
+        .address(mp_contract)
+        .address(&receiver)
+        .number(info.stake)
+        .notify();
+    true
+}
+
+/// allow a deregistered suspended marketplace contract or operator to register again, need admin signature
+pub fn lift_suspension(addr: &Address) -> bool {
+    assert!(check_witness(&get_admin()));
+    assert!(is_suspended(addr));
+    database::delete(utils::generate_suspended_key(addr));
+    EventBuilder::new()
+        .string("liftSuspension")
+        .address(addr)
+        .notify();
+    true
+}
+
+/// query whether the marketplace contract or operator was deregistered while suspended
+pub fn is_suspended(addr: &Address) -> bool {
+    database::get::<_, bool>(utils::generate_suspended_key(addr)).unwrap_or(false)
+}
+
+/// query marketplace info by marketplace contract address
+pub fn get_marketplace(mp_contract: &Address) -> Option<MarketplaceInfo> {
+    database::get::<_, MarketplaceInfo>(utils::generate_marketplace_key(mp_contract))
+}
+
+/// check whether the marketplace is registered and active
+pub fn is_registered(mp_contract: &Address) -> bool {
+    match get_marketplace(mp_contract) {
+        Some(info) => info.status == MpStatus::Active,
+        None => false,
+    }
+}
+
+/// query the number of entries in the marketplace list, the deregistered ones included
+pub fn get_marketplace_count() -> u32 {
+    database::get::<_, u32>(KEY_MARKETPLACE_COUNT).unwrap_or(0)
+}
+
+/// query the registered marketplaces, whatever their status, in the order they registered
+///
+/// at most `limit` list entries are read starting from the `cursor`th one, `limit` is capped at 100,
+/// the entries of deregistered marketplaces are skipped,
+/// so the next page starts from `cursor` plus the capped `limit`
+pub fn get_all_marketplaces(cursor: u32, limit: u32) -> Vec<Address> {
+    let end = get_marketplace_count().min(cursor.saturating_add(limit.min(MAX_PAGE_SIZE)));
+    (cursor..end)
+        .filter_map(|i| database::get::<_, Address>(utils::generate_marketplace_list_key(i)))
+        .collect()
+}
+
+/// query the active marketplaces, paged like `get_all_marketplaces`
+pub fn get_active_marketplaces(cursor: u32, limit: u32) -> Vec<Address> {
+    get_all_marketplaces(cursor, limit)
+        .into_iter()
+        .filter(|addr| is_registered(addr))
+        .collect()
+}
+
+fn update_status(mp_contract: &Address, from: MpStatus, to: MpStatus, event: &str) -> bool {
+    assert!(check_witness(&get_admin()));
+    let mut info = get_marketplace(mp_contract).expect("marketplace not registered");
+    assert!(info.status == from);
+    info.status = to;
+    database::put(utils::generate_marketplace_key(mp_contract), info);
+    EventBuilder::new()
+        .string(event)
+        .address(mp_contract)
+        .notify();
+    true
+}
+
+#[no_mangle]
+pub fn invoke() {
+    let input = input();
+    let mut source = Source::new(&input);
+    let action: &[u8] = source.read().unwrap();
+    let mut sink = Sink::new(12);
+    match action {
+        b"updateAdmin" => {
+            let new_admin = source.read().unwrap();
+            sink.write(update_admin(&new_admin));
+        }
+        b"getAdmin" => {
+            sink.write(get_admin());
+        }
+        b"setMinStake" => {
+            let amount = source.read().unwrap();
+            sink.write(set_min_stake(amount));
+        }
+        b"getMinStake" => {
+            sink.write(get_min_stake());
+        }
+        b"registerMarketplace" => {
+            let (mp_contract, meta_bytes, stake) = source.read().unwrap();
+            sink.write(register_marketplace(mp_contract, meta_bytes, stake));
+        }
+        b"approveMarketplace" => {
+            let mp_contract = source.read().unwrap();
+            sink.write(approve_marketplace(mp_contract));
+        }
+        b"suspendMarketplace" => {
+            let mp_contract = source.read().unwrap();
+            sink.write(suspend_marketplace(mp_contract));
+        }
+        b"resumeMarketplace" => {
+            let mp_contract = source.read().unwrap();
+            sink.write(resume_marketplace(mp_contract));
+        }
+        b"deregisterMarketplace" => {
+            let mp_contract = source.read().unwrap();
+            sink.write(deregister_marketplace(mp_contract));
+        }
+        b"liftSuspension" => {
+            let addr = source.read().unwrap();
+            sink.write(lift_suspension(addr));
+        }
+        b"isSuspended" => {
+            let addr = source.read().unwrap();
+            sink.write(is_suspended(addr));
+        }
+        b"getMarketplace" => {
+            let mp_contract = source.read().unwrap();
+            sink.write(get_marketplace(mp_contract));
+        }
+        b"isRegistered" => {
+            let mp_contract = source.read().unwrap();
+            sink.write(is_registered(mp_contract));
+        }
+        b"getMarketplaceCount" => {
+            sink.write(get_marketplace_count());
+        }
+        b"getAllMarketplaces" => {
+            let (cursor, limit) = source.read().unwrap();
+            sink.write(get_all_marketplaces(cursor, limit));
+        }
+        b"getActiveMarketplaces" => {
+            let (cursor, limit) = source.read().unwrap();
+            sink.write(get_active_marketplaces(cursor, limit));
+        }
+        _ => {
+            let method = str::from_utf8(action).ok().unwrap();
+            panic!("registry contract, not support method:{}", method)
+        }
+    }
+    ret(sink.bytes());
+}
+
+mod utils {
+    use super::*;
+    use alloc::vec::Vec;
+    pub fn generate_marketplace_key(mp_contract: &Address) -> Vec<u8> {
+        [KEY_MARKETPLACE, mp_contract.as_ref()].concat()
+    }
+    pub fn generate_suspended_key(addr: &Address) -> Vec<u8> {
+        [KEY_SUSPENDED, addr.as_ref()].concat()
+    }
+    pub fn generate_marketplace_list_key(index: u32) -> Vec<u8> {
+        [KEY_MARKETPLACE_LIST, index.to_le_bytes().as_ref()].concat()
+    }
+    pub fn generate_marketplace_position_key(mp_contract: &Address) -> Vec<u8> {
+        [KEY_MARKETPLACE_POSITION, mp_contract.as_ref()].concat()
+    }
+}
@@ -0,0 +1,127 @@
+use super::*;
+use ostd::mock::build_runtime;
+use ostd::types::H256;
+
+fn meta(operator: &Address) -> MarketplaceMeta {
+    MarketplaceMeta {
+        name: b"mp".to_vec(),
+        endpoint_hash: H256::repeat_byte(1),
+        operator: operator.clone(),
+        fee_policy: b"10% of each order".to_vec(),
+    }
+}
+
+#[test]
+fn register_with_approval() {
+    let handle = build_runtime();
+    let mp = Address::repeat_byte(1);
+    let operator = Address::repeat_byte(2);
+
+    handle.witness(&[operator.clone(), mp.clone()]);
+    assert!(register_marketplace(&mp, &meta(&operator).to_bytes(), 0));
+    assert!(!is_registered(&mp));
+    assert_eq!(get_all_marketplaces(0, 10), vec![mp.clone()]);
+    assert!(get_active_marketplaces(0, 10).is_empty());
+
+    handle.witness(&[ADMIN.clone()]);
+    assert!(approve_marketplace(&mp));
+    assert!(is_registered(&mp));
+    assert_eq!(get_active_marketplaces(0, 10), vec![mp.clone()]);
+
+    assert!(suspend_marketplace(&mp));
+    assert!(!is_registered(&mp));
+    assert!(resume_marketplace(&mp));
+    assert!(is_registered(&mp));
+
+    handle.witness(&[operator.clone()]);
+    assert!(deregister_marketplace(&mp));
+    assert!(get_marketplace(&mp).is_none());
+    assert!(get_all_marketplaces(0, 10).is_empty());
+    // the entry is left empty
+    assert_eq!(get_marketplace_count(), 1);
+
+    handle.witness(&[operator.clone(), mp.clone()]);
+    assert!(register_marketplace(&mp, &meta(&operator).to_bytes(), 0));
+    assert_eq!(get_marketplace_count(), 2);
+    assert_eq!(get_all_marketplaces(1, 1), vec![mp.clone()]);
+}
+
+#[test]
+fn register_with_stake() {
+    let handle = build_runtime();
+    let mp = Address::repeat_byte(1);
+    let operator = Address::repeat_byte(2);
+    handle.on_contract_call(|_addr: &Address, _data: &[u8]| -> Option<Vec<u8>> { Some(vec![1u8]) });
+
+    handle.witness(&[ADMIN.clone()]);
+    assert!(set_min_stake(100));
+
+    handle.witness(&[operator.clone(), mp.clone()]);
+    assert!(register_marketplace(&mp, &meta(&operator).to_bytes(), 100));
+    assert!(is_registered(&mp));
+    assert_eq!(get_marketplace(&mp).unwrap().stake, 100);
+}
+
+#[test]
+#[should_panic]
+fn register_twice() {
+    let handle = build_runtime();
+    let mp = Address::repeat_byte(1);
+    let operator = Address::repeat_byte(2);
+    handle.witness(&[operator.clone(), mp.clone()]);
+    assert!(register_marketplace(&mp, &meta(&operator).to_bytes(), 0));
+    register_marketplace(&mp, &meta(&operator).to_bytes(), 0);
+}
+
+#[test]
+#[should_panic(expected = "marketplace contract did not authorize the registration")]
+fn register_others_marketplace() {
+    let handle = build_runtime();
+    let mp = Address::repeat_byte(1);
+    let mallory = Address::repeat_byte(9);
+    handle.witness(&[mallory.clone()]);
+    register_marketplace(&mp, &meta(&mallory).to_bytes(), 0);
+}
+
+#[test]
+#[should_panic(expected = "marketplace contract did not authorize the registration")]
+fn register_by_admin_without_marketplace_consent() {
+    let handle = build_runtime();
+    let mp = Address::repeat_byte(1);
+    let operator = Address::repeat_byte(2);
+    handle.witness(&[operator.clone(), ADMIN.clone()]);
+    register_marketplace(&mp, &meta(&operator).to_bytes(), 0);
+}
+
+#[test]
+#[should_panic(expected = "marketplace is suspended")]
+fn deregister_suspended_by_operator() {
+    let handle = build_runtime();
+    let mp = Address::repeat_byte(1);
+    let operator = Address::repeat_byte(2);
+    handle.witness(&[operator.clone(), mp.clone(), ADMIN.clone()]);
+    assert!(register_marketplace(&mp, &meta(&operator).to_bytes(), 0));
+    assert!(approve_marketplace(&mp));
+    assert!(suspend_marketplace(&mp));
+
+    handle.witness(&[operator.clone(), mp.clone()]);
+    deregister_marketplace(&mp);
+}
+
+#[test]
+#[should_panic(expected = "marketplace is suspended")]
+fn register_after_suspended_deregistration() {
+    let handle = build_runtime();
+    let mp = Address::repeat_byte(1);
+    let operator = Address::repeat_byte(2);
+    handle.witness(&[operator.clone(), mp.clone(), ADMIN.clone()]);
+    assert!(register_marketplace(&mp, &meta(&operator).to_bytes(), 0));
+    assert!(approve_marketplace(&mp));
+    assert!(suspend_marketplace(&mp));
+
+    handle.witness(&[ADMIN.clone()]);
+    assert!(deregister_marketplace(&mp));
+    assert!(is_suspended(&mp) && is_suspended(&operator));
+    handle.witness(&[operator.clone(), mp.clone()]);
+    register_marketplace(&mp, &meta(&operator).to_bytes(), 0);
+}
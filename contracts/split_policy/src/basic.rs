@@ -0,0 +1,286 @@
+use super::ostd::abi::{Decoder, Encoder, Error, Sink, Source};
+use super::ostd::prelude::*;
+use super::ostd::types::{Address, U128};
+use super::{share_of, TOTAL};
+use common::{Currency, TokenType};
+
+/// reference to another split policy, in this contract if `contract` is None
+#[derive(Encoder, Decoder, Clone)]
+pub struct SplitRef {
+    pub contract: Option<Address>,
+    pub key: Vec<u8>,
+}
+
+#[derive(Encoder, Decoder, Clone)]
+pub struct AddrAmt {
+    pub to: Address,
+    pub percent: U128,
+    pub split: Option<SplitRef>, // if set, the share is forwarded into that split policy, `to` still approves changes
+    pub vesting: Option<Vesting>,
+    pub min_payout: U128, // smaller amounts accrue in the contract when distributed
+}
+
+impl AddrAmt {
+    /// a payee who is paid directly, without vesting or minimum payout
+    pub fn new(to: Address, percent: U128) -> Self {
+        AddrAmt {
+            to,
+            percent,
+            split: None,
+            vesting: None,
+            min_payout: 0,
+        }
+    }
+
+    /// the share is forwarded into a policy of another split contract
+    pub fn forwards_externally(&self) -> bool {
+        self.split
+            .as_ref()
+            .map(|split| split.contract.is_some())
+            .unwrap_or(false)
+    }
+}
+
+/// vesting schedule of a payee, nothing vests before `start + cliff`,
+/// then the entitlement vests linearly until `start + duration`
+#[derive(Encoder, Decoder, Clone)]
+pub struct Vesting {
+    pub start: u64,
+    pub cliff: u64,
+    pub duration: u64,
+}
+
+impl Vesting {
+    /// the part of `amt` which has vested at `now`
+    pub fn vested(&self, amt: U128, now: u64) -> U128 {
+        if now < self.start.saturating_add(self.cliff) {
+            return 0;
+        }
+        let elapsed = now - self.start;
+        if elapsed >= self.duration {
+            return amt;
+        }
+        amt.checked_mul(elapsed as U128)
+            .unwrap()
+            .checked_div(self.duration as U128)
+            .unwrap()
+    }
+}
+
+#[derive(Encoder, Decoder)]
+pub struct CurrencyAmount {
+    pub currency: Currency,
+    pub amount: U128,
+}
+
+#[derive(Encoder, Decoder)]
+pub struct VestingInfo {
+    pub entitled: U128,
+    pub vested: U128,
+    pub withdrawn: U128,
+    pub unvested: U128,
+}
+
+#[derive(Encoder, Decoder, Clone)]
+pub struct EffectiveShare {
+    pub to: Address,
+    pub percent: U128,
+}
+
+/// who gets the remainder left by the integer division of the shares
+#[derive(Clone)]
+pub enum RemainderRule {
+    FirstPayee,
+    LargestPayee,
+    Designated(Address),
+}
+
+impl Encoder for RemainderRule {
+    fn encode(&self, sink: &mut Sink) {
+        match self {
+            RemainderRule::FirstPayee => {
+                sink.write(0u8);
+            }
+            RemainderRule::LargestPayee => {
+                sink.write(1u8);
+            }
+            RemainderRule::Designated(addr) => {
+                sink.write(2u8);
+                sink.write(addr);
+            }
+        }
+    }
+}
+
+impl<'a> Decoder<'a> for RemainderRule {
+    fn decode(source: &mut Source<'a>) -> Result<Self, Error> {
+        let ty = source.read_byte()?;
+        match ty {
+            0 => Ok(RemainderRule::FirstPayee),
+            1 => Ok(RemainderRule::LargestPayee),
+            2 => Ok(RemainderRule::Designated(source.read()?)),
+            _ => panic!("not support remainder rule:{}", ty),
+        }
+    }
+}
+
+#[derive(Encoder, Decoder, Clone)]
+pub struct RegisterParam {
+    pub addr_amt: Vec<AddrAmt>,
+    pub currencies: Vec<Currency>, // accepted currencies, the first one is the default
+    pub remainder: RemainderRule,
+    pub amend_threshold: U128, // share of the payees which need to approve a change, same unit as percent
+    pub owner: Option<Address>, // can revoke vesting schedules
+    pub auto_distribute: Option<U128>, // the pool is only distributed once what can be paid out reaches it
+    pub reassign_delay: u64, // seconds before a payee can confirm moving the entry to a new address
+}
+
+impl RegisterParam {
+    pub fn default() -> Self {
+        RegisterParam {
+            addr_amt: vec![],
+            currencies: vec![Currency {
+                contract_addr: Address::new([0u8; 20]),
+                contract_type: TokenType::ONG,
+            }],
+            remainder: RemainderRule::FirstPayee,
+            amend_threshold: TOTAL,
+            owner: None,
+            auto_distribute: None,
+            reassign_delay: 0,
+        }
+    }
+    pub fn from_bytes(data: &[u8]) -> RegisterParam {
+        let mut source = Source::new(data);
+        let rp: RegisterParam = source.read().unwrap();
+        rp
+    }
+
+    /// the currency used by the methods which do not name one
+    pub fn default_currency(&self) -> Currency {
+        self.currencies.first().cloned().expect("no currency")
+    }
+
+    pub fn accepts(&self, currency: &Currency) -> bool {
+        self.currencies.contains(currency)
+    }
+
+    /// the payee who gets the remainder, None if the remainder goes to a designated address
+    pub fn remainder_payee(&self) -> Option<&Address> {
+        match self.remainder {
+            RemainderRule::FirstPayee => self.addr_amt.first().map(|aa| &aa.to),
+            RemainderRule::LargestPayee => {
+                let mut largest: Option<&AddrAmt> = None;
+                for aa in self.addr_amt.iter() {
+                    if largest.map(|l| aa.percent > l.percent).unwrap_or(true) {
+                        largest = Some(aa);
+                    }
+                }
+                largest.map(|aa| &aa.to)
+            }
+            RemainderRule::Designated(_) => None,
+        }
+    }
+
+    /// what is left of `amt` after every payee takes the share
+    pub fn dust_of(&self, amt: U128) -> U128 {
+        let shared: U128 = self
+            .addr_amt
+            .iter()
+            .map(|aa| share_of(amt, aa.percent))
+            .sum();
+        amt.checked_sub(shared).unwrap()
+    }
+
+    /// the keys of the policies in this contract the shares are forwarded into
+    pub fn nested_keys(&self) -> Vec<&[u8]> {
+        self.addr_amt
+            .iter()
+            .filter_map(|aa| aa.split.as_ref())
+            .filter(|split| split.contract.is_none())
+            .map(|split| split.key.as_slice())
+            .collect()
+    }
+
+    pub fn vesting_of(&self, addr: &Address) -> Option<&Vesting> {
+        self.addr_amt
+            .iter()
+            .find(|aa| &aa.to == addr)
+            .and_then(|aa| aa.vesting.as_ref())
+    }
+
+    /// the amount of `total` the payee is entitled to, including the remainder if the payee gets it
+    pub fn entitled(&self, total: U128, addr: &Address) -> U128 {
+        match self.addr_amt.iter().find(|aa| &aa.to == addr) {
+            Some(aa) => {
+                let share = share_of(total, aa.percent);
+                if self.remainder_payee() == Some(addr) {
+                    share.checked_add(self.dust_of(total)).unwrap()
+                } else {
+                    share
+                }
+            }
+            None => 0,
+        }
+    }
+}
+
+/// the terms stored before the per currency accounting, only read to migrate them
+#[derive(Encoder, Decoder)]
+pub struct LegacyRegisterParam {
+    pub addr_amt: Vec<LegacyAddrAmt>,
+    pub token_type: TokenType,
+    pub contract_addr: Option<Address>,
+}
+
+#[derive(Encoder, Decoder)]
+pub struct LegacyAddrAmt {
+    pub to: Address,
+    pub percent: U128,
+    pub has_withdraw: bool,
+}
+
+/// what a payee withdrew from a legacy balance
+#[derive(Encoder, Decoder)]
+pub struct LegacyWithdrawal {
+    pub to: Address,
+    pub amount: U128,
+}
+
+#[derive(Encoder, Decoder)]
+pub struct SplitChangeProposal {
+    pub proposer: Address,
+    pub param: RegisterParam,
+    pub approvals: Vec<Address>,
+}
+
+/// terms registered once and shared by many keys
+#[derive(Encoder, Decoder)]
+pub struct SplitTemplate {
+    pub owner: Address, // can update the terms and bind keys
+    pub param: RegisterParam,
+    pub version: u32, // counts the updates, the former terms are kept by version
+}
+
+#[derive(Encoder, Decoder)]
+pub struct TemplateBinding {
+    pub template_id: Vec<u8>,
+    pub snapshot: bool, // the key keeps the terms at binding time instead of following the template
+    pub version: u32,   // the template version the key receives under until it is settled again
+}
+
+#[derive(Encoder, Decoder)]
+pub struct PayeeChange {
+    pub new: Address,
+    pub effective_at: u64,
+}
+
+/// what a payee got from one key in one currency
+#[derive(Encoder, Decoder)]
+pub struct Statement {
+    pub key: Vec<u8>,
+    pub currency: Currency,
+    pub received: U128, // the payee's share of what the key received
+    pub withdrawn: U128,
+    pub claimable: U128,
+}
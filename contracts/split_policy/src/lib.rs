@@ -4,74 +4,127 @@ extern crate ontio_std as ostd;
 use ostd::abi::{EventBuilder, Sink, Source};
 use ostd::database;
 use ostd::prelude::*;
-use ostd::runtime::{address, check_witness, input, ret, storage_read};
+use ostd::runtime::{address, caller, check_witness, input, ret, timestamp};
 extern crate common;
-use common::TokenType;
-use ostd::abi::{Decoder, Encoder};
+use common::{Currency, TokenType};
 use ostd::contract::{ong, ont, wasm};
+mod basic;
+use basic::*;
 
 #[cfg(test)]
 mod test;
 
-const KEY_REGISTRY_PARM: &[u8] = b"01";
-const KEY_BALANCE: &[u8] = b"02";
+// the keys of the policies registered before the per currency accounting
+const KEY_LEGACY_REGISTRY_PARM: &[u8] = b"01";
+const KEY_LEGACY_BALANCE: &[u8] = b"02";
+const KEY_TOTAL_RECEIVED: &[u8] = b"03";
+const KEY_WITHDRAWN: &[u8] = b"04";
+const KEY_DUST_SWEPT: &[u8] = b"05";
+const KEY_EPOCH_START: &[u8] = b"06";
+const KEY_SETTLED: &[u8] = b"07";
+const KEY_SPLIT_CHANGE: &[u8] = b"08";
+const KEY_ADMIN: &[u8] = b"09";
+const KEY_ALLOWED_CALLERS: &[u8] = b"10";
+const KEY_TEMPLATE: &[u8] = b"11";
+const KEY_TEMPLATE_BINDING: &[u8] = b"12";
+const KEY_TEMPLATE_RESOURCES: &[u8] = b"13";
+const KEY_PAYEE_CHANGE: &[u8] = b"14";
+const KEY_PAYEE_KEYS: &[u8] = b"15";
+const KEY_REGISTRY_PARM: &[u8] = b"16";
+const KEY_BALANCE: &[u8] = b"17";
+const KEY_PARENT_LEVELS: &[u8] = b"18";
+const KEY_TEMPLATE_VERSION: &[u8] = b"19";
+const KEY_TEMPLATE_RESOURCE_COUNT: &[u8] = b"20";
+const KEY_PAYEE_KEY_COUNT: &[u8] = b"21";
+const KEY_PAYEE_KEY_POSITION: &[u8] = b"22";
+
+const ADMIN: Address = ostd::macros::base58!("AYnhakv7kC9R5ppw65JoE2rt6xDzCjCTvD");
 
 const TOTAL: U128 = 10000;
+const MAX_SPLIT_DEPTH: u32 = 4;
+const MAX_PAGE_SIZE: u32 = 100;
 
-#[derive(Encoder, Decoder, Clone)]
-pub struct AddrAmt {
-    to: Address,
-    percent: U128,
-    has_withdraw: bool,
+/// need old admin signature
+///
+/// update the admin address, admin has the right to manage the allowed callers
+fn update_admin(new_admin: &Address) -> bool {
+    let old_admin = get_admin();
+    assert!(check_witness(&old_admin));
+    database::put(KEY_ADMIN, new_admin);
+    true
 }
 
-#[derive(Encoder, Decoder)]
-pub struct RegisterParam {
-    addr_amt: Vec<AddrAmt>,
-    token_type: TokenType,
-    contract_addr: Option<Address>,
+/// query admin address
+fn get_admin() -> Address {
+    database::get::<_, Address>(KEY_ADMIN).unwrap_or(ADMIN)
 }
 
-impl RegisterParam {
-    fn default() -> Self {
-        RegisterParam {
-            addr_amt: vec![],
-            token_type: TokenType::ONG,
-            contract_addr: None,
-        }
-    }
-    fn from_bytes(data: &[u8]) -> RegisterParam {
-        let mut source = Source::new(data);
-        let rp: RegisterParam = source.read().unwrap();
-        rp
-    }
+/// allow a contract to invoke `transfer` and `transferWithdraw` on behalf of the payer,
+/// such as the marketplace or ddxf contract, need admin signature
+pub fn add_allowed_caller(contract: &Address) -> bool {
+    assert!(check_witness(&get_admin()));
+    let mut callers = get_allowed_callers();
+    assert!(!callers.contains(contract));
+    callers.push(contract.clone());
+    database::put(KEY_ALLOWED_CALLERS, callers);
+    EventBuilder::new()
+        .string("addAllowedCaller")
+        .address(contract)
+        .notify();
+    true
+}
+
+/// remove a contract from the allowed callers, need admin signature
+pub fn remove_allowed_caller(contract: &Address) -> bool {
+    assert!(check_witness(&get_admin()));
+    let mut callers = get_allowed_callers();
+    assert!(callers.contains(contract));
+    callers.retain(|addr| addr != contract);
+    database::put(KEY_ALLOWED_CALLERS, callers);
+    EventBuilder::new()
+        .string("removeAllowedCaller")
+        .address(contract)
+        .notify();
+    true
+}
+
+/// query the contracts which are allowed to pay on behalf of the payer
+pub fn get_allowed_callers() -> Vec<Address> {
+    database::get::<_, Vec<Address>>(KEY_ALLOWED_CALLERS).unwrap_or(vec![])
+}
+
+/// the payer signs the transaction, or an allowed contract pays on behalf of the payer
+fn check_payer(from: &Address) {
+    assert!(
+        get_allowed_callers().contains(&caller()) || check_witness(from),
+        "unauthorized payer"
+    );
 }
 
 /// register the dividend distribution strategy on the chain
 ///
 /// `key` is also called resource_id in the other contract, used to mark the uniqueness of dividend strategy
 ///
-/// `param_bytes` is the serialization result of RegisterParam
+/// `param_bytes` is the serialization result of RegisterParam, need the owner signature if the owner is set
 pub fn register(key: &[u8], param_bytes: &[u8]) -> bool {
     let param = RegisterParam::from_bytes(param_bytes);
-    let data = storage_read(key.as_ref()).map(|val: Vec<u8>| val);
-    assert!(data.is_none());
-    match param.token_type {
-        TokenType::OEP4 => {
-            assert!(param.contract_addr.is_some());
-        }
-        _ => {}
-    }
-    let mut total: U128 = 0;
+    assert!(find_register_param(key).is_none());
+    check_param(&param);
+    let level = get_level(key);
+    check_nested(key, &param, level);
+    check_nested_consent(None, &param);
     let mut valid = false;
     for aa in param.addr_amt.iter() {
-        total += aa.percent;
         if !valid {
             valid = check_witness(&aa.to);
         }
     }
     assert!(valid);
-    assert_eq!(total, TOTAL);
+    if let Some(owner) = param.owner.as_ref() {
+        assert!(check_witness(owner));
+    }
+    index_payees(key, &param);
+    link_nested(&param, level);
     database::put(generate_registry_param_key(key), param);
     EventBuilder::new()
         .string("register")
@@ -80,94 +133,1210 @@ pub fn register(key: &[u8], param_bytes: &[u8]) -> bool {
     true
 }
 
-/// query RegisterParam by key
+fn check_param(param: &RegisterParam) {
+    assert!(!param.currencies.is_empty());
+    for (i, currency) in param.currencies.iter().enumerate() {
+        assert!(!param.currencies[..i].contains(currency));
+    }
+    let mut total: U128 = 0;
+    for (i, aa) in param.addr_amt.iter().enumerate() {
+        assert!(param.addr_amt[..i].iter().all(|other| other.to != aa.to));
+        if let Some(vesting) = aa.vesting.as_ref() {
+            assert!(aa.split.is_none(), "nested split policy can not vest");
+            assert!(vesting.cliff <= vesting.duration);
+        }
+        total += aa.percent;
+    }
+    assert_eq!(total, TOTAL);
+    assert!(param.amend_threshold != 0 && param.amend_threshold <= TOTAL);
+}
+
+/// a payee of a policy in this contract can be another policy of this contract, it must
+/// accept every currency of the parent, must not lead back to `key` and must not nest too deep
+///
+/// a payee can also be a policy of another split contract, which must be registered there and
+/// accept every currency of the parent
+///
+/// `depth` is the level of `key`, so that the policies forwarding into it are counted as well
+fn check_nested(key: &[u8], param: &RegisterParam, depth: u32) {
+    assert!(depth < MAX_SPLIT_DEPTH, "split policy nests too deep");
+    for aa in param.addr_amt.iter() {
+        if let Some(split) = aa.split.as_ref() {
+            match split.contract {
+                None => {
+                    assert!(split.key.as_slice() != key, "split policy cycle");
+                    let child = find_register_param(&split.key)
+                        .expect("nested split policy not registered");
+                    assert!(param
+                        .currencies
+                        .iter()
+                        .all(|currency| child.accepts(currency)));
+                    check_nested(key, &child, depth + 1);
+                }
+                Some(contract) => {
+                    let res = wasm::call_contract(
+                        &contract,
+                        ("get_register_param", split.key.as_slice()),
+                    )
+                    .expect("nested split policy not registered");
+                    let child = RegisterParam::from_bytes(&res);
+                    assert!(param
+                        .currencies
+                        .iter()
+                        .all(|currency| child.accepts(currency)));
+                }
+            }
+        }
+    }
+}
+
+/// the payees holding `amend_threshold` of the shares of every policy of this contract which
+/// `param` newly forwards into must sign, so that a policy can not be nested without its consent
+///
+/// `former` is the policy `param` replaces
+fn check_nested_consent(former: Option<&RegisterParam>, param: &RegisterParam) {
+    let linked = former.map(|rp| rp.nested_keys()).unwrap_or(vec![]);
+    for child in param.nested_keys() {
+        if !linked.contains(&child) {
+            assert!(
+                signed_by_threshold(&get_register_param(child)),
+                "nested split policy did not consent"
+            );
+        }
+    }
+}
+
+/// the payees who sign hold `amend_threshold` of the shares
+fn signed_by_threshold(rp: &RegisterParam) -> bool {
+    let signed: U128 = rp
+        .addr_amt
+        .iter()
+        .filter(|aa| check_witness(&aa.to))
+        .map(|aa| aa.percent)
+        .sum();
+    signed >= rp.amend_threshold
+}
+
+/// the longest chain of policies of this contract which forward into the key, 0 if none does
+fn get_level(key: &[u8]) -> u32 {
+    level_of(&get_parent_levels(key))
+}
+
+fn level_of(parent_levels: &[u32]) -> u32 {
+    parent_levels
+        .iter()
+        .rposition(|n| *n != 0)
+        .map(|i| i as u32 + 1)
+        .unwrap_or(0)
+}
+
+/// the number of references into the key, by the level of the referencing policy
+fn get_parent_levels(key: &[u8]) -> Vec<u32> {
+    database::get::<_, Vec<u32>>(generate_parent_levels_key(key)).unwrap_or(vec![])
+}
+
+/// record that the policy at `level` forwards into the policies of `param`
+fn link_nested(param: &RegisterParam, level: u32) {
+    for child in param.nested_keys() {
+        move_parent_level(child, None, Some(level));
+    }
+}
+
+/// the policy at `level` no longer forwards into the policies of `param`
+fn unlink_nested(param: &RegisterParam, level: u32) {
+    for child in param.nested_keys() {
+        move_parent_level(child, Some(level), None);
+    }
+}
+
+/// stop the policy `parent` forwarding into the key, need the signatures of the payees holding
+/// `amend_threshold` of the shares of the key
+///
+/// the shares of `parent` which were forwarded into the key are paid to their payees directly,
+/// a parent following a template keeps the current terms of the template from then on
+pub fn detach_parent(key: &[u8], parent: &[u8]) -> bool {
+    let rp = get_register_param(key);
+    assert!(signed_by_threshold(&rp), "not enough payee signatures");
+    let mut parent_rp = get_register_param(parent);
+    let level = get_level(parent);
+    let mut detached = 0;
+    for aa in parent_rp.addr_amt.iter_mut() {
+        let forwards = aa
+            .split
+            .as_ref()
+            .map(|split| split.contract.is_none() && split.key.as_slice() == key)
+            .unwrap_or(false);
+        if forwards {
+            aa.split = None;
+            detached += 1;
+        }
+    }
+    assert_ne!(detached, 0, "split policy does not forward into the key");
+    for _ in 0..detached {
+        move_parent_level(key, Some(level), None);
+    }
+    if let Some(mut binding) = get_template_binding(parent) {
+        binding.snapshot = true;
+        database::put(generate_template_binding_key(parent), binding);
+    }
+    // a pending change of the parent which forwards into the key was not consented to
+    if get_split_change(parent)
+        .map(|proposal| proposal.param.nested_keys().contains(&key))
+        .unwrap_or(false)
+    {
+        database::delete(generate_split_change_key(parent));
+    }
+    database::put(generate_registry_param_key(parent), parent_rp);
+    EventBuilder::new()
+        .string("detachParent")
+        .bytearray(key)
+        .bytearray(parent)
+        .notify();
+    true
+}
+
+/// move a reference into the key from one parent level to another, the levels of the policies
+/// below follow if the level of the key changes
+fn move_parent_level(key: &[u8], from: Option<u32>, to: Option<u32>) {
+    let mut levels = get_parent_levels(key);
+    let old = level_of(&levels);
+    if let Some(from) = from {
+        levels[from as usize] -= 1;
+    }
+    if let Some(to) = to {
+        if levels.len() <= to as usize {
+            levels.resize(to as usize + 1, 0);
+        }
+        levels[to as usize] += 1;
+    }
+    while levels.last() == Some(&0) {
+        levels.pop();
+    }
+    let new = level_of(&levels);
+    if levels.is_empty() {
+        database::delete(generate_parent_levels_key(key));
+    } else {
+        database::put(generate_parent_levels_key(key), levels);
+    }
+    if new != old {
+        if let Some(param) = find_register_param(key) {
+            for child in param.nested_keys() {
+                move_parent_level(child, Some(old), Some(new));
+            }
+        }
+    }
+}
+
+/// propose to replace the split policy of the key, need proposer signature
+///
+/// `proposer` must be one of the current payees, the proposal counts as approved by the proposer
+///
+/// `param_bytes` is the serialization result of the new RegisterParam, currencies can be added
+/// but not removed
+///
+/// a former proposal of the key is discarded
+pub fn propose_split_change(key: &[u8], proposer: &Address, param_bytes: &[u8]) -> bool {
+    assert!(check_witness(proposer));
+    assert!(
+        !is_following_template(key),
+        "split policy follows a template"
+    );
+    let rp = get_register_param(key);
+    assert!(rp.addr_amt.iter().any(|aa| &aa.to == proposer));
+    let param = RegisterParam::from_bytes(param_bytes);
+    check_param(&param);
+    check_nested(key, &param, get_level(key));
+    check_nested_consent(Some(&rp), &param);
+    assert!(rp.currencies.iter().all(|currency| param.accepts(currency)));
+    let proposal = SplitChangeProposal {
+        proposer: proposer.clone(),
+        param,
+        approvals: vec![proposer.clone()],
+    };
+    EventBuilder::new()
+        .string("proposeSplitChange")
+        .bytearray(key)
+        .address(proposer)
+        .notify();
+    try_apply_split_change(key, rp, proposal);
+    true
+}
+
+/// approve the pending split change of the key, need approver signature
+///
+/// `approver` must be one of the current payees, the change is applied once the approvers
+/// hold `amend_threshold` of the current shares
+pub fn approve_split_change(key: &[u8], approver: &Address) -> bool {
+    assert!(check_witness(approver));
+    let rp = get_register_param(key);
+    assert!(rp.addr_amt.iter().any(|aa| &aa.to == approver));
+    let mut proposal = get_split_change(key).expect("no split change proposal");
+    assert!(!proposal.approvals.contains(approver));
+    proposal.approvals.push(approver.clone());
+    EventBuilder::new()
+        .string("approveSplitChange")
+        .bytearray(key)
+        .address(approver)
+        .notify();
+    try_apply_split_change(key, rp, proposal);
+    true
+}
+
+/// query the pending split change of the key
+pub fn get_split_change(key: &[u8]) -> Option<SplitChangeProposal> {
+    database::get::<_, SplitChangeProposal>(generate_split_change_key(key))
+}
+
+fn try_apply_split_change(key: &[u8], rp: RegisterParam, proposal: SplitChangeProposal) {
+    let approved: U128 = rp
+        .addr_amt
+        .iter()
+        .filter(|aa| proposal.approvals.contains(&aa.to))
+        .map(|aa| aa.percent)
+        .sum();
+    if approved < rp.amend_threshold {
+        database::put(generate_split_change_key(key), proposal);
+        return;
+    }
+    // the policies forwarding into the key may have changed since the proposal
+    let level = get_level(key);
+    check_nested(key, &proposal.param, level);
+    settle_epoch(key, &rp);
+    database::delete(generate_split_change_key(key));
+    index_payees(key, &proposal.param);
+    unlink_nested(&rp, level);
+    link_nested(&proposal.param, level);
+    database::put(generate_registry_param_key(key), proposal.param);
+    EventBuilder::new()
+        .string("splitChanged")
+        .bytearray(key)
+        .notify();
+}
+
+/// settle what has been received in every currency under the current terms, the payees
+/// keep what they are entitled to even if they are not in the new terms
+fn settle_epoch(key: &[u8], rp: &RegisterParam) {
+    for currency in rp.currencies.iter() {
+        let amt = epoch_received(key, currency);
+        for aa in rp.addr_amt.iter() {
+            add_settled(key, &aa.to, currency, rp.entitled(amt, &aa.to));
+        }
+        if let RemainderRule::Designated(addr) = rp.remainder {
+            let dust = rp
+                .dust_of(amt)
+                .checked_sub(get_dust_swept(key, currency))
+                .unwrap();
+            add_settled(key, &addr, currency, dust);
+            database::delete(generate_dust_swept_key(key, currency));
+        }
+        database::put(
+            generate_epoch_start_key(key, currency),
+            get_total_received(key, currency),
+        );
+    }
+}
+
+fn add_settled(key: &[u8], addr: &Address, currency: &Currency, amt: U128) {
+    if amt == 0 {
+        return;
+    }
+    let settled = get_settled(key, addr, currency).checked_add(amt).unwrap();
+    database::put(generate_settled_key(key, addr, currency), settled);
+}
+
+fn set_settled(key: &[u8], addr: &Address, currency: &Currency, amt: U128) {
+    if amt == 0 {
+        database::delete(generate_settled_key(key, addr, currency));
+    } else {
+        database::put(generate_settled_key(key, addr, currency), amt);
+    }
+}
+
+fn get_settled(key: &[u8], addr: &Address, currency: &Currency) -> U128 {
+    database::get::<_, U128>(generate_settled_key(key, addr, currency)).unwrap_or(0)
+}
+
+/// what has been received since the current terms took effect
+fn epoch_received(key: &[u8], currency: &Currency) -> U128 {
+    let start = database::get::<_, U128>(generate_epoch_start_key(key, currency)).unwrap_or(0);
+    get_total_received(key, currency)
+        .checked_sub(start)
+        .unwrap()
+}
+
+/// query the vesting state of the payee in the currency, a payee without vesting schedule
+/// has vested everything
+pub fn get_vesting(key: &[u8], addr: &Address, currency: &Currency) -> VestingInfo {
+    let rp = get_register_param(key);
+    let entitled = entitled(key, &rp, addr, currency);
+    let vested = vested(key, &rp, addr, currency);
+    VestingInfo {
+        entitled,
+        vested,
+        withdrawn: get_withdrawn(key, addr, currency),
+        unvested: entitled.checked_sub(vested).unwrap(),
+    }
+}
+
+/// revoke the vesting schedule of the payee, need the policy owner signature
+///
+/// the payee keeps what has vested, the unvested part is shared by the remaining payees,
+/// the payee is removed from the policy and the percent of the others is scaled up
+pub fn revoke_vesting(key: &[u8], addr: &Address) -> bool {
+    assert!(
+        !is_following_template(key),
+        "split policy follows a template"
+    );
+    let rp = get_register_param(key);
+    let owner = rp.owner.clone().expect("split policy has no owner");
+    assert!(check_witness(&owner));
+    let revoked = rp
+        .addr_amt
+        .iter()
+        .find(|aa| &aa.to == addr)
+        .expect("not found the addr");
+    let vesting = revoked.vesting.clone().expect("payee has no vesting");
+    let rest = TOTAL.checked_sub(revoked.percent).unwrap();
+    assert_ne!(rest, 0, "no payee left");
+    settle_epoch(key, &rp);
+
+    let mut param = RegisterParam {
+        addr_amt: rp
+            .addr_amt
+            .iter()
+            .filter(|aa| &aa.to != addr)
+            .cloned()
+            .collect(),
+        ..rp
+    };
+    let mut scaled: U128 = 0;
+    for aa in param.addr_amt.iter_mut() {
+        aa.percent = aa.percent.checked_mul(TOTAL).unwrap() / rest;
+        scaled += aa.percent;
+    }
+    param.addr_amt[0].percent += TOTAL - scaled;
+
+    for currency in param.currencies.iter() {
+        let settled = get_settled(key, addr, currency);
+        let vested = vesting.vested(settled, timestamp());
+        let unvested = settled.checked_sub(vested).unwrap();
+        set_settled(key, addr, currency, vested);
+        for aa in param.addr_amt.iter() {
+            add_settled(key, &aa.to, currency, param.entitled(unvested, &aa.to));
+        }
+        if let RemainderRule::Designated(to) = param.remainder {
+            add_settled(key, &to, currency, param.dust_of(unvested));
+        }
+        EventBuilder::new()
+            .string("revokeVesting")
+            .bytearray(key)
+            .address(addr)
+            .bytearray(&currency.to_key_bytes())
+            .number(unvested)
+            .notify();
+    }
+    database::delete(generate_split_change_key(key));
+    database::put(generate_registry_param_key(key), param);
+    true
+}
+
+/// move the entry of payee `old` to the address `new`, need old signature
+///
+/// the change is applied at once if the policy has no reassign delay, otherwise it can be
+/// confirmed by `confirm_payee_change` once the delay has passed, a former request is replaced
+pub fn request_payee_change(key: &[u8], old: &Address, new: &Address) -> bool {
+    assert!(check_witness(old));
+    assert!(
+        !is_following_template(key),
+        "split policy follows a template"
+    );
+    let rp = get_register_param(key);
+    assert!(
+        rp.addr_amt.iter().any(|aa| &aa.to == old),
+        "not found the addr"
+    );
+    assert!(rp.addr_amt.iter().all(|aa| &aa.to != new));
+    EventBuilder::new()
+        .string("requestPayeeChange")
+        .bytearray(key)
+        .address(old)
+        .address(new)
+        .notify();
+    if rp.reassign_delay == 0 {
+        apply_payee_change(key, rp, old, new);
+    } else {
+        let change = PayeeChange {
+            new: new.clone(),
+            effective_at: timestamp().checked_add(rp.reassign_delay).unwrap(),
+        };
+        database::put(generate_payee_change_key(key, old), change);
+    }
+    true
+}
+
+/// apply the pending change of payee `old` once the delay has passed, anyone can invoke this method
+pub fn confirm_payee_change(key: &[u8], old: &Address) -> bool {
+    let change = get_payee_change(key, old).expect("no payee change");
+    assert!(
+        timestamp() >= change.effective_at,
+        "payee change is not effective yet"
+    );
+    let rp = get_register_param(key);
+    assert!(
+        rp.addr_amt.iter().any(|aa| &aa.to == old),
+        "not found the addr"
+    );
+    assert!(rp.addr_amt.iter().all(|aa| aa.to != change.new));
+    database::delete(generate_payee_change_key(key, old));
+    apply_payee_change(key, rp, old, &change.new);
+    true
+}
+
+/// cancel the pending change of payee `old`, need old signature
+pub fn cancel_payee_change(key: &[u8], old: &Address) -> bool {
+    assert!(check_witness(old));
+    assert!(get_payee_change(key, old).is_some());
+    database::delete(generate_payee_change_key(key, old));
+    EventBuilder::new()
+        .string("cancelPayeeChange")
+        .bytearray(key)
+        .address(old)
+        .notify();
+    true
+}
+
+/// query the pending change of payee `old`
+pub fn get_payee_change(key: &[u8], old: &Address) -> Option<PayeeChange> {
+    database::get::<_, PayeeChange>(generate_payee_change_key(key, old))
+}
+
+/// the new address takes over what the old one is entitled to and has withdrawn
+fn apply_payee_change(key: &[u8], mut rp: RegisterParam, old: &Address, new: &Address) {
+    settle_epoch(key, &rp);
+    for currency in rp.currencies.iter() {
+        add_settled(key, new, currency, get_settled(key, old, currency));
+        set_settled(key, old, currency, 0);
+        let withdrawn = get_withdrawn(key, old, currency);
+        if withdrawn != 0 {
+            let total = get_withdrawn(key, new, currency)
+                .checked_add(withdrawn)
+                .unwrap();
+            database::put(generate_withdrawn_key(key, new, currency), total);
+            database::delete(generate_withdrawn_key(key, old, currency));
+        }
+    }
+    for aa in rp.addr_amt.iter_mut() {
+        if &aa.to == old {
+            aa.to = new.clone();
+        }
+    }
+    index_payees(key, &rp);
+    unindex_payee(old, key);
+    // a pending split change was approved with the old address and may still list it
+    database::delete(generate_split_change_key(key));
+    database::put(generate_registry_param_key(key), rp);
+    EventBuilder::new()
+        .string("payeeChanged")
+        .bytearray(key)
+        .address(old)
+        .address(new)
+        .notify();
+}
+
+fn index_payees(key: &[u8], rp: &RegisterParam) {
+    for aa in rp.addr_amt.iter() {
+        index_payee(&aa.to, key);
+    }
+}
+
+fn index_payee(addr: &Address, key: &[u8]) {
+    let position_key = generate_payee_key_position_key(addr, key);
+    if database::get::<_, u32>(&position_key).is_some() {
+        return;
+    }
+    let count = get_payee_key_count(addr);
+    database::put(generate_payee_keys_key(addr, count), key);
+    database::put(position_key, count);
+    database::put(generate_payee_key_count_key(addr), count + 1);
+}
+
+/// the entry is left empty, so that the positions of the other keys do not move
+fn unindex_payee(addr: &Address, key: &[u8]) {
+    let position_key = generate_payee_key_position_key(addr, key);
+    if let Some(index) = database::get::<_, u32>(&position_key) {
+        database::delete(generate_payee_keys_key(addr, index));
+        database::delete(position_key);
+    }
+}
+
+/// query the number of entries in the payee index of the address, including the emptied ones
+pub fn get_payee_key_count(addr: &Address) -> u32 {
+    database::get::<_, u32>(generate_payee_key_count_key(addr)).unwrap_or(0)
+}
+
+/// query the keys the address has been a payee of, from the entry `start` of the index,
+/// at most `limit` entries are read and the keys the address moved away from are skipped
+pub fn get_payee_keys(addr: &Address, start: u32, limit: u32) -> Vec<Vec<u8>> {
+    let end = get_payee_key_count(addr).min(start.saturating_add(limit.min(MAX_PAGE_SIZE)));
+    (start..end)
+        .filter_map(|i| database::get::<_, Vec<u8>>(generate_payee_keys_key(addr, i)))
+        .collect()
+}
+
+/// query what the address received, withdrew and can claim, for every key and currency
+///
+/// `start` and `limit` page through the payee index like `get_payee_keys`
+pub fn get_statement(addr: &Address, start: u32, limit: u32) -> Vec<Statement> {
+    let mut statements = vec![];
+    for key in get_payee_keys(addr, start, limit).into_iter() {
+        let rp = get_register_param(&key);
+        for currency in rp.currencies.iter() {
+            statements.push(Statement {
+                received: entitled(&key, &rp, addr, currency),
+                withdrawn: get_withdrawn(&key, addr, currency),
+                claimable: claimable(&key, &rp, addr, currency),
+                currency: currency.clone(),
+                key: key.clone(),
+            });
+        }
+    }
+    statements
+}
+
+/// query RegisterParam by key, panic if the key is not registered
+///
+/// a key bound to a template follows the current terms of the template, unless it is a snapshot
 pub fn get_register_param(key: &[u8]) -> RegisterParam {
-    database::get::<_, RegisterParam>(generate_registry_param_key(key))
-        .unwrap_or(RegisterParam::default())
+    find_register_param(key).expect("split policy not registered")
 }
 
-pub fn transfer(from: &Address, key: &[u8], amt: U128) -> bool {
+fn find_register_param(key: &[u8]) -> Option<RegisterParam> {
+    if let Some(param) = database::get::<_, RegisterParam>(generate_registry_param_key(key)) {
+        return Some(param);
+    }
+    if let Some(legacy) =
+        database::get::<_, LegacyRegisterParam>(generate_legacy_registry_param_key(key))
+    {
+        return Some(migrate_legacy(key, legacy));
+    }
+    let mut binding = get_template_binding(key)?;
+    let template = get_template(&binding.template_id)?;
+    if binding.version != template.version {
+        // what the key received since it was last settled was under the terms it followed then
+        let former = get_template_version(&binding.template_id, binding.version);
+        settle_epoch(key, &former);
+        index_payees(key, &template.param);
+        binding.version = template.version;
+        database::put(generate_template_binding_key(key), binding);
+    }
+    Some(template.param)
+}
+
+/// move the terms of a policy registered before the per currency accounting to the current layout
+///
+/// the legacy balance is left for `migrate_legacy_balance`, the legacy `has_withdraw` was also set
+/// by `transferWithdraw`, which paid the payees without touching the balance, so the terms do not
+/// tell what each payee has withdrawn from it
+fn migrate_legacy(key: &[u8], legacy: LegacyRegisterParam) -> RegisterParam {
+    let currency = Currency {
+        contract_addr: legacy.contract_addr.unwrap_or(Address::new([0u8; 20])),
+        contract_type: legacy.token_type,
+    };
+    let param = RegisterParam {
+        addr_amt: legacy
+            .addr_amt
+            .iter()
+            .map(|aa| AddrAmt::new(aa.to.clone(), aa.percent))
+            .collect(),
+        currencies: vec![currency],
+        ..RegisterParam::default()
+    };
+    database::delete(generate_legacy_registry_param_key(key));
+    index_payees(key, &param);
+    database::put(generate_registry_param_key(key), param.clone());
+    param
+}
+
+/// query the balance of a policy registered before the per currency accounting,
+/// which is not moved to the current layout yet
+pub fn get_legacy_balance(key: &[u8]) -> U128 {
+    database::get::<_, U128>(generate_legacy_balance_key(key)).unwrap_or(0)
+}
+
+/// move the legacy balance of the key into its pool in the default currency, need admin signature
+///
+/// the legacy balance was never reduced by withdrawals, so it is what the key has received,
+/// `withdrawals` are what the payees have withdrawn from it, as found in the transfer history,
+/// the rest is credited to the pool and shared by all the payees
+pub fn migrate_legacy_balance(key: &[u8], withdrawals: Vec<LegacyWithdrawal>) -> bool {
+    assert!(check_witness(&get_admin()));
+    let received = get_legacy_balance(key);
+    assert_ne!(received, 0, "no legacy balance");
+    let rp = get_register_param(key);
+    let currency = rp.default_currency();
+    let mut balance = received;
+    for w in withdrawals.iter() {
+        assert!(
+            w.amount <= rp.entitled(received, &w.to),
+            "withdrawn more than the share"
+        );
+        assert_eq!(get_withdrawn(key, &w.to, &currency), 0);
+        balance = balance.checked_sub(w.amount).unwrap();
+        database::put(generate_withdrawn_key(key, &w.to, &currency), w.amount);
+    }
+    let total = get_total_received(key, &currency)
+        .checked_add(received)
+        .unwrap();
+    database::put(generate_total_received_key(key, &currency), total);
+    let pool = get_balance(key, &currency).checked_add(balance).unwrap();
+    database::put(generate_balance_key(key, &currency), pool);
+    database::delete(generate_legacy_balance_key(key));
+    EventBuilder::new()
+        .string("migrateLegacyBalance")
+        .bytearray(key)
+        .number(received)
+        .number(balance)
+        .notify();
+    true
+}
+
+/// register split terms under `template_id`, which can be bound to many keys, need owner signature
+///
+/// `param_bytes` is the serialization result of RegisterParam
+pub fn register_template(template_id: &[u8], owner: &Address, param_bytes: &[u8]) -> bool {
+    assert!(check_witness(owner));
+    assert!(get_template(template_id).is_none());
+    let param = RegisterParam::from_bytes(param_bytes);
+    check_param(&param);
+    let template = SplitTemplate {
+        owner: owner.clone(),
+        param,
+        version: 0,
+    };
+    database::put(generate_template_key(template_id), template);
+    EventBuilder::new()
+        .string("registerTemplate")
+        .bytearray(template_id)
+        .address(owner)
+        .notify();
+    true
+}
+
+/// update the terms of the template, need the template owner signature
+///
+/// what the keys following the template have received so far is settled under the former terms
+/// the next time each key is used, currencies can be added but not removed and the nested
+/// split policies in this contract can not be changed
+pub fn update_template(template_id: &[u8], param_bytes: &[u8]) -> bool {
+    let mut template = get_template(template_id).expect("template not registered");
+    assert!(check_witness(&template.owner));
+    let param = RegisterParam::from_bytes(param_bytes);
+    check_param(&param);
+    assert!(template
+        .param
+        .currencies
+        .iter()
+        .all(|currency| param.accepts(currency)));
+    assert!(
+        param.nested_keys() == template.param.nested_keys(),
+        "nested split policies of a template can not be changed"
+    );
+    for child in param.nested_keys() {
+        let child = get_register_param(child);
+        assert!(param
+            .currencies
+            .iter()
+            .all(|currency| child.accepts(currency)));
+    }
+    database::put(
+        generate_template_version_key(template_id, template.version),
+        template.param,
+    );
+    template.param = param;
+    template.version += 1;
+    database::put(generate_template_key(template_id), template);
+    EventBuilder::new()
+        .string("updateTemplate")
+        .bytearray(template_id)
+        .notify();
+    true
+}
+
+/// the terms of the template at `version`
+fn get_template_version(template_id: &[u8], version: u32) -> RegisterParam {
+    let template = get_template(template_id).expect("template not registered");
+    if version == template.version {
+        return template.param;
+    }
+    database::get::<_, RegisterParam>(generate_template_version_key(template_id, version))
+        .expect("template version not found")
+}
+
+/// bind the key to the template instead of registering its own terms, need the template owner signature
+///
+/// `snapshot` copies the current terms of the template to the key, otherwise the key follows
+/// the template when it is updated
+pub fn bind_template(key: &[u8], template_id: &[u8], snapshot: bool) -> bool {
+    assert!(find_register_param(key).is_none());
+    let template = get_template(template_id).expect("template not registered");
+    assert!(check_witness(&template.owner));
+    let level = get_level(key);
+    check_nested(key, &template.param, level);
+    check_nested_consent(None, &template.param);
+    index_payees(key, &template.param);
+    link_nested(&template.param, level);
+    if snapshot {
+        database::put(generate_registry_param_key(key), template.param);
+    }
+    let binding = TemplateBinding {
+        template_id: template_id.to_vec(),
+        snapshot,
+        version: template.version,
+    };
+    database::put(generate_template_binding_key(key), binding);
+    let count = get_template_resource_count(template_id);
+    database::put(generate_template_resources_key(template_id, count), key);
+    database::put(generate_template_resource_count_key(template_id), count + 1);
+    EventBuilder::new()
+        .string("bindTemplate")
+        .bytearray(key)
+        .bytearray(template_id)
+        .notify();
+    true
+}
+
+/// query the template by template id
+pub fn get_template(template_id: &[u8]) -> Option<SplitTemplate> {
+    database::get::<_, SplitTemplate>(generate_template_key(template_id))
+}
+
+/// query the template the key is bound to
+pub fn get_template_binding(key: &[u8]) -> Option<TemplateBinding> {
+    database::get::<_, TemplateBinding>(generate_template_binding_key(key))
+}
+
+/// query the number of keys bound to the template, including the snapshots
+pub fn get_template_resource_count(template_id: &[u8]) -> u32 {
+    database::get::<_, u32>(generate_template_resource_count_key(template_id)).unwrap_or(0)
+}
+
+/// query the keys bound to the template in the order of binding, at most `limit` keys from `start`
+pub fn get_template_resources(template_id: &[u8], start: u32, limit: u32) -> Vec<Vec<u8>> {
+    let end = get_template_resource_count(template_id)
+        .min(start.saturating_add(limit.min(MAX_PAGE_SIZE)));
+    (start..end)
+        .filter_map(|i| {
+            database::get::<_, Vec<u8>>(generate_template_resources_key(template_id, i))
+        })
+        .collect()
+}
+
+/// the terms of a key following a template can only be changed through the template
+fn is_following_template(key: &[u8]) -> bool {
+    get_template_binding(key)
+        .map(|binding| !binding.snapshot)
+        .unwrap_or(false)
+}
+
+/// transfer token into the contract, the amount is shared by the payees according to their percent
+///
+/// `from` is the payer address
+///
+/// `key` is also called resource_id in the other contract
+///
+/// `currency` must be one of the currencies the policy accepts
+///
+/// need the payer signature, unless it is invoked by an allowed caller
+pub fn transfer(from: &Address, key: &[u8], currency: &Currency, amt: U128) -> bool {
+    check_payer(from);
     let self_addr = address();
     let param = get_register_param(key);
-    assert!(transfer_inner(
-        from,
-        &self_addr,
-        amt,
-        &param.token_type,
-        param.contract_addr
-    ));
-    let balance = get_balance(key);
-    let balance = balance.checked_add(amt).unwrap();
-    database::put(generate_balance_key(key), balance);
+    assert!(param.accepts(currency), "currency not accepted");
+    assert!(transfer_inner(from, &self_addr, amt, currency));
+    credit(key, currency, amt);
     true
 }
 
-pub fn get_balance(key: &[u8]) -> U128 {
-    database::get::<_, U128>(generate_balance_key(key)).unwrap_or(0)
+fn credit(key: &[u8], currency: &Currency, amt: U128) {
+    let total = get_total_received(key, currency);
+    database::put(
+        generate_total_received_key(key, currency),
+        total.checked_add(amt).unwrap(),
+    );
+    let balance = get_balance(key, currency);
+    let balance = balance.checked_add(amt).unwrap();
+    database::put(generate_balance_key(key, currency), balance);
+}
+
+/// query the amount of the currency held by the contract for the key, which has not been withdrawn
+pub fn get_balance(key: &[u8], currency: &Currency) -> U128 {
+    database::get::<_, U128>(generate_balance_key(key, currency)).unwrap_or(0)
 }
 
-/// the data owner withdraw token from the contract
+/// query the balance of the key in every currency the policy accepts
+pub fn get_balances(key: &[u8]) -> Vec<CurrencyAmount> {
+    let rp = get_register_param(key);
+    rp.currencies
+        .into_iter()
+        .map(|currency| CurrencyAmount {
+            amount: get_balance(key, &currency),
+            currency,
+        })
+        .collect()
+}
+
+/// query the amount of the currency the key has received since registration
+pub fn get_total_received(key: &[u8], currency: &Currency) -> U128 {
+    database::get::<_, U128>(generate_total_received_key(key, currency)).unwrap_or(0)
+}
+
+/// query the amount of the currency the payee has withdrawn from the key
+pub fn get_withdrawn(key: &[u8], addr: &Address, currency: &Currency) -> U128 {
+    database::get::<_, U128>(generate_withdrawn_key(key, addr, currency)).unwrap_or(0)
+}
+
+/// query the amount the payee can withdraw now, that is the vested part of the payee's share
+/// of the total received, including what was settled under former terms, minus what has been withdrawn
+pub fn get_claimable(key: &[u8], addr: &Address, currency: &Currency) -> U128 {
+    let rp = get_register_param(key);
+    claimable(key, &rp, addr, currency)
+}
+
+/// query the amount the payee can withdraw now in every currency the policy accepts
+pub fn get_claimables(key: &[u8], addr: &Address) -> Vec<CurrencyAmount> {
+    let rp = get_register_param(key);
+    rp.currencies
+        .iter()
+        .map(|currency| CurrencyAmount {
+            amount: claimable(key, &rp, addr, currency),
+            currency: currency.clone(),
+        })
+        .collect()
+}
+
+/// the data owner withdraw token from the contract, can be invoked again when new sales arrive
+///
+/// every currency the payee can claim is withdrawn, see `withdraw_currency` to withdraw one currency
+///
+/// if the payee is a nested split policy, the share is forwarded into that policy instead
 ///
 /// `key` is also called resource_id in the other contract
 ///
 /// `addr` is the address who withdraw token, need the address signature
 pub fn withdraw(key: &[u8], addr: &Address) -> bool {
     assert!(check_witness(addr));
-    let mut rp = get_register_param(key);
-    let index = rp.addr_amt.iter().position(|addr_amt| &addr_amt.to == addr);
-    if let Some(ind) = index {
-        let addr_amt = rp.addr_amt.get_mut(ind).unwrap();
-        if addr_amt.has_withdraw == false {
-            let self_addr = address();
-            let balance = get_balance(key);
-            let temp = balance.checked_mul(addr_amt.percent).unwrap();
-            let amt = temp.checked_div(TOTAL).unwrap();
-            assert!(transfer_inner(
-                &self_addr,
-                &addr_amt.to,
-                amt,
-                &rp.token_type,
-                rp.contract_addr
-            ));
-            addr_amt.has_withdraw = true;
-            database::put(generate_registry_param_key(key), rp);
-            return true;
-        } else {
-            panic!("has withdraw")
-        }
-    }
-    panic!("not found the addr")
+    let rp = get_register_param(key);
+    check_payee(key, &rp, addr);
+    let mut paid = false;
+    for currency in rp.currencies.iter() {
+        if pay_out(key, &rp, addr, currency, None) != 0 {
+            paid = true;
+        }
+    }
+    assert!(paid, "nothing to withdraw");
+    true
+}
+
+/// withdraw the currency from the contract, need the address signature
+pub fn withdraw_currency(key: &[u8], addr: &Address, currency: &Currency) -> bool {
+    assert!(check_witness(addr));
+    let rp = get_register_param(key);
+    check_payee(key, &rp, addr);
+    let amt = pay_out(key, &rp, addr, currency, None);
+    assert_ne!(amt, 0, "nothing to withdraw");
+    true
+}
+
+fn check_payee(key: &[u8], rp: &RegisterParam, addr: &Address) {
+    if !rp.addr_amt.iter().any(|addr_amt| &addr_amt.to == addr)
+        && rp
+            .currencies
+            .iter()
+            .all(|currency| get_settled(key, addr, currency) == 0)
+    {
+        panic!("not found the addr")
+    }
 }
 
 //mp invoke
-pub fn transfer_withdraw(from: &Address, key: &[u8], amt: U128) -> bool {
-    let mut rp = get_register_param(key);
-    for addr_amt in rp.addr_amt.iter_mut() {
-        if !addr_amt.has_withdraw {
-            let temp = amt.checked_mul(addr_amt.percent).unwrap();
-            let temp = temp.checked_div(TOTAL).unwrap();
-            assert!(transfer_inner(
-                from,
-                &addr_amt.to,
-                temp,
-                &rp.token_type,
-                rp.contract_addr
-            ));
-            addr_amt.has_withdraw = true;
+/// transfer token into the contract and pay every payee what they can claim,
+/// the shares of nested split policies in this contract are distributed further
+///
+/// if the policy has an auto distribute threshold, the amount accrues in the contract
+/// until what can be paid out reaches it
+///
+/// need the payer signature, unless it is invoked by an allowed caller
+pub fn transfer_withdraw(from: &Address, key: &[u8], currency: &Currency, amt: U128) -> bool {
+    assert!(transfer(from, key, currency, amt));
+    if reached_threshold(key, currency) {
+        distribute_inner(key, currency, 0);
+    }
+    true
+}
+
+fn reached_threshold(key: &[u8], currency: &Currency) -> bool {
+    let rp = get_register_param(key);
+    rp.auto_distribute
+        .map(|threshold| distributable(key, &rp, currency) >= threshold)
+        .unwrap_or(true)
+}
+
+/// what distributing the currency would pay out now, unvested amounts, claims below
+/// the minimum payout of the payee and shares forwarded into other contracts stay in the contract
+fn distributable(key: &[u8], rp: &RegisterParam, currency: &Currency) -> U128 {
+    rp.addr_amt
+        .iter()
+        .filter(|aa| !aa.forwards_externally())
+        .map(|aa| {
+            let amt = claimable(key, rp, &aa.to, currency);
+            if amt >= aa.min_payout {
+                amt
+            } else {
+                0
+            }
+        })
+        .sum()
+}
+
+/// pay every payee what they can claim, anyone can invoke this method
+/// once what can be paid out reaches the auto distribute threshold
+///
+/// each currency is distributed on its own once it reaches the threshold,
+/// payees whose claimable amount is below their minimum payout are skipped
+pub fn distribute(key: &[u8]) -> bool {
+    let rp = get_register_param(key);
+    let mut distributed = false;
+    for currency in rp.currencies.iter() {
+        if get_balance(key, currency) != 0 && reached_threshold(key, currency) {
+            distribute_inner(key, currency, 0);
+            distributed = true;
         }
     }
-    database::put(generate_registry_param_key(key), rp);
+    assert!(distributed, "balance below the distribute threshold");
     true
 }
 
-fn transfer_inner(
-    from: &Address,
-    to: &Address,
-    amt: U128,
-    token_type: &TokenType,
-    contract: Option<Address>,
-) -> bool {
-    match token_type {
+/// query the effective percent of every final payee, with nested split policies flattened
+///
+/// the percent has the same unit as AddrAmt.percent and is rounded down
+pub fn get_effective_split(key: &[u8]) -> Vec<EffectiveShare> {
+    get_nested_effective_split(key, 0)
+}
+
+/// query the effective split of a policy another split contract forwards into at `depth`,
+/// the depth is carried across the contracts so that a cycle through them ends
+pub fn get_nested_effective_split(key: &[u8], depth: u32) -> Vec<EffectiveShare> {
+    let mut shares = vec![];
+    flatten(key, TOTAL, depth, &mut shares);
+    shares
+}
+
+fn flatten(key: &[u8], weight: U128, depth: u32, shares: &mut Vec<EffectiveShare>) {
+    assert!(depth < MAX_SPLIT_DEPTH, "split policy nests too deep");
+    let rp = get_register_param(key);
+    for aa in rp.addr_amt.iter() {
+        let percent = share_of(weight, aa.percent);
+        match aa.split.as_ref() {
+            Some(split) => match split.contract {
+                None => flatten(&split.key, percent, depth + 1, shares),
+                Some(contract) => {
+                    let res = wasm::call_contract(
+                        &contract,
+                        ("getNestedEffectiveSplit", (split.key.as_slice(), depth + 1)),
+                    )
+                    .expect("call nested split contract failed");
+                    let mut source = Source::new(&res);
+                    let nested: Vec<EffectiveShare> = source.read().unwrap();
+                    for share in nested.iter() {
+                        add_effective_share(shares, &share.to, share_of(percent, share.percent));
+                    }
+                }
+            },
+            None => add_effective_share(shares, &aa.to, percent),
+        }
+    }
+}
+
+fn add_effective_share(shares: &mut Vec<EffectiveShare>, to: &Address, percent: U128) {
+    match shares.iter_mut().find(|share| &share.to == to) {
+        Some(share) => share.percent += percent,
+        None => shares.push(EffectiveShare {
+            to: to.clone(),
+            percent,
+        }),
+    }
+}
+
+/// the shares forwarded into policies of other contracts accrue until `forward_external_share`,
+/// so that a failing contract can not stop the purchases
+fn distribute_inner(key: &[u8], currency: &Currency, depth: u32) {
+    let rp = get_register_param(key);
+    for addr_amt in rp.addr_amt.iter().filter(|aa| !aa.forwards_externally()) {
+        if claimable(key, &rp, &addr_amt.to, currency) >= addr_amt.min_payout {
+            pay_out(key, &rp, &addr_amt.to, currency, Some(depth));
+        }
+    }
+}
+
+/// forward the share of the payee into the policy of another split contract it refers to,
+/// anyone can invoke this method
+pub fn forward_external_share(key: &[u8], addr: &Address, currency: &Currency) -> bool {
+    let rp = get_register_param(key);
+    assert!(
+        rp.addr_amt
+            .iter()
+            .any(|aa| &aa.to == addr && aa.forwards_externally()),
+        "share is not forwarded into another contract"
+    );
+    let amt = pay_out(key, &rp, addr, currency, None);
+    assert_ne!(amt, 0, "nothing to forward");
+    true
+}
+
+/// query the remainder of the shares in the currency which has not been swept to the designated address
+///
+/// it is always 0 if the remainder goes to a payee
+pub fn get_dust(key: &[u8], currency: &Currency) -> U128 {
+    let rp = get_register_param(key);
+    undistributed_dust(key, &rp, currency)
+}
+
+/// sweep the remainder of the shares in the currency to the designated address of the RemainderRule
+///
+/// anyone can invoke this method, the token only goes to the designated address
+pub fn sweep_dust(key: &[u8], currency: &Currency) -> bool {
+    let rp = get_register_param(key);
+    let to = match rp.remainder {
+        RemainderRule::Designated(addr) => addr,
+        _ => panic!("remainder is not designated to an address"),
+    };
+    let amt = undistributed_dust(key, &rp, currency);
+    assert_ne!(amt, 0, "no dust");
+    assert!(transfer_inner(&address(), &to, amt, currency));
+    let swept = get_dust_swept(key, currency).checked_add(amt).unwrap();
+    database::put(generate_dust_swept_key(key, currency), swept);
+    let balance = get_balance(key, currency).checked_sub(amt).unwrap();
+    database::put(generate_balance_key(key, currency), balance);
+    EventBuilder::new()
+        .string("sweepDust")
+        .bytearray(key)
+        .address(&to)
+        .bytearray(&currency.to_key_bytes())
+        .number(amt)
+        .notify();
+    true
+}
+
+fn get_dust_swept(key: &[u8], currency: &Currency) -> U128 {
+    database::get::<_, U128>(generate_dust_swept_key(key, currency)).unwrap_or(0)
+}
+
+fn undistributed_dust(key: &[u8], rp: &RegisterParam, currency: &Currency) -> U128 {
+    match rp.remainder {
+        RemainderRule::Designated(_) => {
+            let dust = rp.dust_of(epoch_received(key, currency));
+            dust.checked_sub(get_dust_swept(key, currency)).unwrap()
+        }
+        _ => 0,
+    }
+}
+
+fn share_of(amt: U128, percent: U128) -> U128 {
+    amt.checked_mul(percent).unwrap().checked_div(TOTAL).unwrap()
+}
+
+fn entitled(key: &[u8], rp: &RegisterParam, addr: &Address, currency: &Currency) -> U128 {
+    let entitled = rp.entitled(epoch_received(key, currency), addr);
+    entitled
+        .checked_add(get_settled(key, addr, currency))
+        .unwrap()
+}
+
+fn vested(key: &[u8], rp: &RegisterParam, addr: &Address, currency: &Currency) -> U128 {
+    let entitled = entitled(key, rp, addr, currency);
+    match rp.vesting_of(addr) {
+        Some(vesting) => vesting.vested(entitled, timestamp()),
+        None => entitled,
+    }
+}
+
+/// the unvested part stays in the contract until it vests
+fn claimable(key: &[u8], rp: &RegisterParam, addr: &Address, currency: &Currency) -> U128 {
+    vested(key, rp, addr, currency).saturating_sub(get_withdrawn(key, addr, currency))
+}
+
+/// pay what the payee can claim, `cascade` is the depth of `key` if a forwarded share
+/// should be distributed further, None if it should stay in the nested policy
+fn pay_out(
+    key: &[u8],
+    rp: &RegisterParam,
+    addr: &Address,
+    currency: &Currency,
+    cascade: Option<u32>,
+) -> U128 {
+    let amt = claimable(key, rp, addr, currency);
+    if amt == 0 {
+        return 0;
+    }
+    let withdrawn = get_withdrawn(key, addr, currency).checked_add(amt).unwrap();
+    database::put(generate_withdrawn_key(key, addr, currency), withdrawn);
+    let balance = get_balance(key, currency).checked_sub(amt).unwrap();
+    database::put(generate_balance_key(key, currency), balance);
+    let nested = rp
+        .addr_amt
+        .iter()
+        .find(|aa| &aa.to == addr)
+        .and_then(|aa| aa.split.as_ref());
+    match nested {
+        Some(split) => forward(split, currency, amt, cascade),
+        None => {
+            assert!(transfer_inner(&address(), addr, amt, currency));
+        }
+    }
+    EventBuilder::new()
+        .string("withdraw")
+        .bytearray(key)
+        .address(addr)
+        .bytearray(&currency.to_key_bytes())
+        .number(amt)
+        .notify();
+    amt
+}
+
+fn forward(split: &SplitRef, currency: &Currency, amt: U128, cascade: Option<u32>) {
+    match split.contract {
+        None => {
+            credit(&split.key, currency, amt);
+            if let Some(depth) = cascade {
+                assert!(depth + 1 < MAX_SPLIT_DEPTH, "split policy nests too deep");
+                if reached_threshold(&split.key, currency) {
+                    distribute_inner(&split.key, currency, depth + 1);
+                }
+            }
+        }
+        Some(contract) => {
+            // the nested contract must allow this contract as a caller, the share is not
+            // distributed further there, so that a cycle through the contracts can not recurse
+            let res = wasm::call_contract(
+                &contract,
+                (
+                    "transferCurrency",
+                    (address(), split.key.as_slice(), currency, amt),
+                ),
+            );
+            if let Some(r) = res {
+                let mut source = Source::new(r.as_slice());
+                let rr: bool = source.read().unwrap();
+                assert!(rr);
+            } else {
+                panic!("call nested split contract failed");
+            }
+        }
+    }
+}
+
+fn transfer_inner(from: &Address, to: &Address, amt: U128, currency: &Currency) -> bool {
+    match currency.contract_type {
         TokenType::ONG => {
             assert!(ong::transfer(from, to, amt));
         }
@@ -175,27 +1344,104 @@ fn transfer_inner(
             assert!(ont::transfer(from, to, amt));
         }
         TokenType::OEP4 => {
-            //TODO
-            if let Some(contract_addr) = contract {
-                let res =
-                    wasm::call_contract(&contract_addr, ("transfer", (from, to, amt))).unwrap();
-                let mut source = Source::new(&res);
-                let r: bool = source.read().unwrap();
-                assert!(r);
-                return true;
-            }
-            panic!("not reachable");
+            let res = wasm::call_contract(&currency.contract_addr, ("transfer", (from, to, amt)))
+                .unwrap();
+            let mut source = Source::new(&res);
+            let r: bool = source.read().unwrap();
+            assert!(r);
         }
     }
     true
 }
 
+fn generate_legacy_registry_param_key(key: &[u8]) -> Vec<u8> {
+    [KEY_LEGACY_REGISTRY_PARM, key].concat()
+}
+
+fn generate_legacy_balance_key(key: &[u8]) -> Vec<u8> {
+    [KEY_LEGACY_BALANCE, key].concat()
+}
+
 fn generate_registry_param_key(key: &[u8]) -> Vec<u8> {
     [KEY_REGISTRY_PARM, key].concat()
 }
 
-fn generate_balance_key(key: &[u8]) -> Vec<u8> {
-    [KEY_BALANCE, key].concat()
+fn generate_balance_key(key: &[u8], currency: &Currency) -> Vec<u8> {
+    [KEY_BALANCE, &currency.to_key_bytes(), key].concat()
+}
+
+fn generate_total_received_key(key: &[u8], currency: &Currency) -> Vec<u8> {
+    [KEY_TOTAL_RECEIVED, &currency.to_key_bytes(), key].concat()
+}
+
+fn generate_withdrawn_key(key: &[u8], addr: &Address, currency: &Currency) -> Vec<u8> {
+    [KEY_WITHDRAWN, &currency.to_key_bytes(), key, addr.as_ref()].concat()
+}
+
+fn generate_dust_swept_key(key: &[u8], currency: &Currency) -> Vec<u8> {
+    [KEY_DUST_SWEPT, &currency.to_key_bytes(), key].concat()
+}
+
+fn generate_epoch_start_key(key: &[u8], currency: &Currency) -> Vec<u8> {
+    [KEY_EPOCH_START, &currency.to_key_bytes(), key].concat()
+}
+
+fn generate_settled_key(key: &[u8], addr: &Address, currency: &Currency) -> Vec<u8> {
+    [KEY_SETTLED, &currency.to_key_bytes(), key, addr.as_ref()].concat()
+}
+
+fn generate_split_change_key(key: &[u8]) -> Vec<u8> {
+    [KEY_SPLIT_CHANGE, key].concat()
+}
+
+fn generate_payee_change_key(key: &[u8], old: &Address) -> Vec<u8> {
+    [KEY_PAYEE_CHANGE, old.as_ref(), key].concat()
+}
+
+fn generate_payee_keys_key(addr: &Address, index: u32) -> Vec<u8> {
+    [KEY_PAYEE_KEYS, addr.as_ref(), index.to_le_bytes().as_ref()].concat()
+}
+
+fn generate_payee_key_count_key(addr: &Address) -> Vec<u8> {
+    [KEY_PAYEE_KEY_COUNT, addr.as_ref()].concat()
+}
+
+fn generate_payee_key_position_key(addr: &Address, key: &[u8]) -> Vec<u8> {
+    [KEY_PAYEE_KEY_POSITION, addr.as_ref(), key].concat()
+}
+
+fn generate_template_key(template_id: &[u8]) -> Vec<u8> {
+    [KEY_TEMPLATE, template_id].concat()
+}
+
+fn generate_template_binding_key(key: &[u8]) -> Vec<u8> {
+    [KEY_TEMPLATE_BINDING, key].concat()
+}
+
+fn generate_parent_levels_key(key: &[u8]) -> Vec<u8> {
+    [KEY_PARENT_LEVELS, key].concat()
+}
+
+fn generate_template_resources_key(template_id: &[u8], index: u32) -> Vec<u8> {
+    [
+        KEY_TEMPLATE_RESOURCES,
+        template_id,
+        index.to_le_bytes().as_ref(),
+    ]
+    .concat()
+}
+
+fn generate_template_resource_count_key(template_id: &[u8]) -> Vec<u8> {
+    [KEY_TEMPLATE_RESOURCE_COUNT, template_id].concat()
+}
+
+fn generate_template_version_key(template_id: &[u8], version: u32) -> Vec<u8> {
+    [
+        KEY_TEMPLATE_VERSION,
+        template_id,
+        version.to_le_bytes().as_ref(),
+    ]
+    .concat()
 }
 
 #[no_mangle]
@@ -205,29 +1451,196 @@ pub fn invoke() {
     let action: &[u8] = source.read().unwrap();
     let mut sink = Sink::new(12);
     match action {
+        b"updateAdmin" => {
+            let new_admin = source.read().unwrap();
+            sink.write(update_admin(&new_admin));
+        }
+        b"getAdmin" => {
+            sink.write(get_admin());
+        }
+        b"addAllowedCaller" => {
+            let contract = source.read().unwrap();
+            sink.write(add_allowed_caller(&contract));
+        }
+        b"removeAllowedCaller" => {
+            let contract = source.read().unwrap();
+            sink.write(remove_allowed_caller(&contract));
+        }
+        b"getAllowedCallers" => {
+            sink.write(get_allowed_callers());
+        }
         b"register" => {
             let (key, param_bytes) = source.read().unwrap();
             sink.write(register(key, param_bytes));
         }
+        b"requestPayeeChange" => {
+            let (key, old, new): (&[u8], Address, Address) = source.read().unwrap();
+            sink.write(request_payee_change(key, &old, &new));
+        }
+        b"confirmPayeeChange" => {
+            let (key, old): (&[u8], Address) = source.read().unwrap();
+            sink.write(confirm_payee_change(key, &old));
+        }
+        b"cancelPayeeChange" => {
+            let (key, old): (&[u8], Address) = source.read().unwrap();
+            sink.write(cancel_payee_change(key, &old));
+        }
+        b"getPayeeChange" => {
+            let (key, old): (&[u8], Address) = source.read().unwrap();
+            sink.write(get_payee_change(key, &old));
+        }
+        b"getPayeeKeys" => {
+            let (addr, start, limit) = source.read().unwrap();
+            sink.write(get_payee_keys(&addr, start, limit));
+        }
+        b"getPayeeKeyCount" => {
+            let addr = source.read().unwrap();
+            sink.write(get_payee_key_count(&addr));
+        }
+        b"getStatement" => {
+            let (addr, start, limit) = source.read().unwrap();
+            sink.write(get_statement(&addr, start, limit));
+        }
+        b"registerTemplate" => {
+            let (template_id, owner, param_bytes): (&[u8], Address, &[u8]) = source.read().unwrap();
+            sink.write(register_template(template_id, &owner, param_bytes));
+        }
+        b"updateTemplate" => {
+            let (template_id, param_bytes) = source.read().unwrap();
+            sink.write(update_template(template_id, param_bytes));
+        }
+        b"bindTemplate" => {
+            let (key, template_id, snapshot) = source.read().unwrap();
+            sink.write(bind_template(key, template_id, snapshot));
+        }
+        b"getTemplate" => {
+            let template_id = source.read().unwrap();
+            sink.write(get_template(template_id));
+        }
+        b"getTemplateBinding" => {
+            let key = source.read().unwrap();
+            sink.write(get_template_binding(key));
+        }
+        b"getTemplateResources" => {
+            let (template_id, start, limit) = source.read().unwrap();
+            sink.write(get_template_resources(template_id, start, limit));
+        }
+        b"getTemplateResourceCount" => {
+            let template_id = source.read().unwrap();
+            sink.write(get_template_resource_count(template_id));
+        }
         b"get_register_param" => {
             let key = source.read().unwrap();
             sink.write(get_register_param(key));
         }
         b"transfer" => {
             let (from, key, amt): (Address, &[u8], U128) = source.read().unwrap();
-            sink.write(transfer(&from, key, amt));
+            let currency = get_register_param(key).default_currency();
+            sink.write(transfer(&from, key, &currency, amt));
+        }
+        b"transferCurrency" => {
+            let (from, key, currency, amt): (Address, &[u8], Currency, U128) =
+                source.read().unwrap();
+            sink.write(transfer(&from, key, &currency, amt));
         }
         b"get_balance" => {
             let key = source.read().unwrap();
-            sink.write(get_balance(key));
+            let currency = get_register_param(key).default_currency();
+            sink.write(get_balance(key, &currency));
+        }
+        b"getBalances" => {
+            let key = source.read().unwrap();
+            sink.write(get_balances(key));
+        }
+        b"getTotalReceived" => {
+            let (key, currency): (&[u8], Currency) = source.read().unwrap();
+            sink.write(get_total_received(key, &currency));
+        }
+        b"getWithdrawn" => {
+            let (key, addr, currency): (&[u8], Address, Currency) = source.read().unwrap();
+            sink.write(get_withdrawn(key, &addr, &currency));
+        }
+        b"getClaimable" => {
+            let (key, addr, currency): (&[u8], Address, Currency) = source.read().unwrap();
+            sink.write(get_claimable(key, &addr, &currency));
+        }
+        b"getClaimables" => {
+            let (key, addr): (&[u8], Address) = source.read().unwrap();
+            sink.write(get_claimables(key, &addr));
+        }
+        b"getVesting" => {
+            let (key, addr, currency): (&[u8], Address, Currency) = source.read().unwrap();
+            sink.write(get_vesting(key, &addr, &currency));
+        }
+        b"revokeVesting" => {
+            let (key, addr): (&[u8], Address) = source.read().unwrap();
+            sink.write(revoke_vesting(key, &addr));
+        }
+        b"proposeSplitChange" => {
+            let (key, proposer, param_bytes): (&[u8], Address, &[u8]) = source.read().unwrap();
+            sink.write(propose_split_change(key, &proposer, param_bytes));
+        }
+        b"approveSplitChange" => {
+            let (key, approver): (&[u8], Address) = source.read().unwrap();
+            sink.write(approve_split_change(key, &approver));
+        }
+        b"getSplitChange" => {
+            let key = source.read().unwrap();
+            sink.write(get_split_change(key));
+        }
+        b"getEffectiveSplit" => {
+            let key = source.read().unwrap();
+            sink.write(get_effective_split(key));
+        }
+        b"getNestedEffectiveSplit" => {
+            let (key, depth) = source.read().unwrap();
+            sink.write(get_nested_effective_split(key, depth));
+        }
+        b"distribute" => {
+            let key = source.read().unwrap();
+            sink.write(distribute(key));
+        }
+        b"forwardExternalShare" => {
+            let (key, addr, currency): (&[u8], Address, Currency) = source.read().unwrap();
+            sink.write(forward_external_share(key, &addr, &currency));
+        }
+        b"detachParent" => {
+            let (key, parent) = source.read().unwrap();
+            sink.write(detach_parent(key, parent));
+        }
+        b"getDust" => {
+            let (key, currency): (&[u8], Currency) = source.read().unwrap();
+            sink.write(get_dust(key, &currency));
+        }
+        b"sweepDust" => {
+            let (key, currency): (&[u8], Currency) = source.read().unwrap();
+            sink.write(sweep_dust(key, &currency));
+        }
+        b"getLegacyBalance" => {
+            let key = source.read().unwrap();
+            sink.write(get_legacy_balance(key));
+        }
+        b"migrateLegacyBalance" => {
+            let (key, withdrawals) = source.read().unwrap();
+            sink.write(migrate_legacy_balance(key, withdrawals));
         }
         b"withdraw" => {
             let (key, addr): (&[u8], Address) = source.read().unwrap();
             sink.write(withdraw(key, &addr));
         }
+        b"withdrawCurrency" => {
+            let (key, addr, currency): (&[u8], Address, Currency) = source.read().unwrap();
+            sink.write(withdraw_currency(key, &addr, &currency));
+        }
         b"transferWithdraw" => {
             let (from, key, amt): (Address, &[u8], U128) = source.read().unwrap();
-            sink.write(transfer_withdraw(&from, key, amt));
+            let currency = get_register_param(key).default_currency();
+            sink.write(transfer_withdraw(&from, key, &currency, amt));
+        }
+        b"transferWithdrawCurrency" => {
+            let (from, key, currency, amt): (Address, &[u8], Currency, U128) =
+                source.read().unwrap();
+            sink.write(transfer_withdraw(&from, key, &currency, amt));
         }
         _ => {
             let method = str::from_utf8(action).ok().unwrap();
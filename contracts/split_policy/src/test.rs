@@ -1,10 +1,20 @@
+use super::ostd::database;
 use super::ostd::mock::build_runtime;
 use super::{
-    get_balance, get_register_param, register, transfer, withdraw, AddrAmt, RegisterParam,
+    add_allowed_caller, approve_split_change, bind_template, confirm_payee_change, detach_parent,
+    distribute, forward_external_share, generate_legacy_balance_key,
+    generate_legacy_registry_param_key, get_balance, get_balances, get_claimable, get_claimables,
+    get_dust, get_dust_swept, get_effective_split, get_legacy_balance, get_payee_change,
+    get_payee_keys, get_register_param, get_split_change, get_statement, get_template_resources,
+    get_total_received, get_vesting, get_withdrawn, migrate_legacy_balance, propose_split_change,
+    register, register_template, request_payee_change, revoke_vesting, sweep_dust, transfer,
+    transfer_withdraw, update_template, withdraw, withdraw_currency, AddrAmt, LegacyAddrAmt,
+    LegacyRegisterParam, LegacyWithdrawal, RegisterParam, RemainderRule, SplitRef, Vesting, ADMIN,
+    TOTAL,
 };
-use common::TokenType;
+use common::{Currency, TokenType};
 use ontio_std::abi::{Sink, Source};
-use ontio_std::types::Address;
+use ontio_std::types::{Address, U128};
 
 #[test]
 fn test_registry() {
@@ -21,21 +31,11 @@ fn test_registry() {
 fn test_registry2() {
     let addr1 = Address::repeat_byte(1);
     let addr2 = Address::repeat_byte(2);
-    let aa1 = AddrAmt {
-        to: addr1,
-        percent: 1000,
-        has_withdraw: false,
-    };
-    let aa2 = AddrAmt {
-        to: addr2.clone(),
-        percent: 9000,
-        has_withdraw: false,
-    };
-    let contract_addr = Address::repeat_byte(3);
+    let aa1 = AddrAmt::new(addr1, 1000);
+    let aa2 = AddrAmt::new(addr2.clone(), 9000);
     let rp = RegisterParam {
         addr_amt: vec![aa1.clone(), aa2],
-        token_type: TokenType::ONG,
-        contract_addr: Some(contract_addr),
+        ..RegisterParam::default()
     };
     let mut sink = Sink::new(64);
     sink.write(rp);
@@ -52,18 +52,1044 @@ fn test_registry2() {
 
     let call_contract = move |_addr: &Address, _data: &[u8]| -> Option<Vec<u8>> { Some(vec![1u8]) };
     handle.on_contract_call(call_contract);
-    assert!(transfer(&from, key, 100));
-    assert_eq!(get_balance(key), 100);
+    assert!(transfer(&from, key, &ong(), 100));
+    assert_eq!(get_balance(key, &ong()), 100);
 
     handle.witness(&[addr1.clone()]);
     assert!(withdraw(key, &addr1));
-    let rp = get_register_param(key);
-    assert!(rp.addr_amt[0].has_withdraw);
+    assert_eq!(get_withdrawn(key, &addr1, &ong()), 10);
+    assert_eq!(get_claimable(key, &addr1, &ong()), 0);
+    assert_eq!(get_claimable(key, &addr2, &ong()), 90);
 
-    assert_eq!(rp.addr_amt[1].has_withdraw, false);
+    // the second sale is shared again
+    handle.witness(&[from.clone()]);
+    assert!(transfer(&from, key, &ong(), 100));
+    assert_eq!(get_total_received(key, &ong()), 200);
+
+    handle.witness(&[addr1.clone()]);
+    assert!(withdraw(key, &addr1));
+    assert_eq!(get_withdrawn(key, &addr1, &ong()), 20);
 
     handle.witness(&[addr2.clone()]);
     assert!(withdraw(key, &addr2));
+    assert_eq!(get_withdrawn(key, &addr2, &ong()), 180);
+    assert_eq!(get_balance(key, &ong()), 0);
+}
+
+#[test]
+#[should_panic]
+fn test_withdraw_nothing() {
+    let key: &[u8] = b"01";
+    let handle = build_runtime();
+    let payees = payees();
+    handle.witness(&[payees[0].clone()]);
+    register_three(key, &payees, RemainderRule::FirstPayee);
+    withdraw(key, &payees[0]);
+}
+
+#[test]
+fn test_transfer_withdraw_every_sale() {
+    let key: &[u8] = b"01";
+    let handle = build_runtime();
+    let payees = payees();
+    handle.witness(&[payees[0].clone()]);
+    register_three(key, &payees, RemainderRule::FirstPayee);
+    let from = Address::repeat_byte(9);
+    handle.witness(&[from.clone()]);
+    handle.on_contract_call(|_addr: &Address, _data: &[u8]| -> Option<Vec<u8>> { Some(vec![1u8]) });
+    for _ in 0..3 {
+        assert!(transfer_withdraw(&from, key, &ong(), 100));
+    }
+    assert_eq!(get_withdrawn(key, &payees[0], &ong()), 101);
+    assert_eq!(get_withdrawn(key, &payees[1], &ong()), 99);
+    assert_eq!(get_withdrawn(key, &payees[2], &ong()), 100);
+    assert_eq!(get_balance(key, &ong()), 0);
+}
+
+#[test]
+fn test_remainder_to_largest_payee() {
+    let key: &[u8] = b"01";
+    let handle = build_runtime();
+    let payees = payees();
+    handle.witness(&[payees[0].clone()]);
+    register_three(key, &payees, RemainderRule::LargestPayee);
+    let from = Address::repeat_byte(9);
+    handle.witness(&[from.clone()]);
+    handle.on_contract_call(|_addr: &Address, _data: &[u8]| -> Option<Vec<u8>> { Some(vec![1u8]) });
+    assert!(transfer_withdraw(&from, key, &ong(), 100));
+    assert_eq!(get_withdrawn(key, &payees[0], &ong()), 33);
+    assert_eq!(get_withdrawn(key, &payees[2], &ong()), 34);
+    assert_eq!(get_balance(key, &ong()), 0);
+}
+
+#[test]
+fn test_sweep_dust() {
+    let key: &[u8] = b"01";
+    let handle = build_runtime();
+    let payees = payees();
+    let designated = Address::repeat_byte(8);
+    handle.witness(&[payees[0].clone()]);
+    register_three(key, &payees, RemainderRule::Designated(designated));
+    let from = Address::repeat_byte(9);
+    handle.witness(&[from.clone()]);
+    handle.on_contract_call(|_addr: &Address, _data: &[u8]| -> Option<Vec<u8>> { Some(vec![1u8]) });
+    assert!(transfer_withdraw(&from, key, &ong(), 100));
+    assert!(transfer_withdraw(&from, key, &ong(), 100));
+    assert_eq!(get_dust(key, &ong()), 2);
+    assert_eq!(get_balance(key, &ong()), 2);
+    assert!(sweep_dust(key, &ong()));
+    assert_eq!(get_dust(key, &ong()), 0);
+    assert_eq!(get_balance(key, &ong()), 0);
+}
+
+/// random sales, withdrawals and sweeps never lose token: the contract balance always equals
+/// what has been received minus what has been withdrawn and swept, which is what the payees
+/// can claim plus the dust of the designated address
+#[test]
+fn test_no_dust_lost() {
+    let key: &[u8] = b"01";
+    let handle = build_runtime();
+    let payees = payees();
+    handle.witness(&[payees[0].clone()]);
+    register_three(key, &payees, RemainderRule::Designated(Address::repeat_byte(8)));
+    let from = Address::repeat_byte(9);
+    let mut witnesses = payees.clone();
+    witnesses.push(from.clone());
+    handle.witness(&witnesses);
+    handle.on_contract_call(|_addr: &Address, _data: &[u8]| -> Option<Vec<u8>> { Some(vec![1u8]) });
+
+    let mut seed: u64 = 7;
+    let mut next = move || {
+        seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        seed >> 33
+    };
+    for _ in 0..500 {
+        let amt = (next() % 1000 + 1) as U128;
+        match next() % 3 {
+            0 => assert!(transfer(&from, key, &ong(), amt)),
+            1 => assert!(transfer_withdraw(&from, key, &ong(), amt)),
+            _ => {
+                let payee = &payees[(next() % 3) as usize];
+                if get_claimable(key, payee, &ong()) != 0 {
+                    assert!(withdraw(key, payee));
+                } else if get_dust(key, &ong()) != 0 {
+                    assert!(sweep_dust(key, &ong()));
+                }
+            }
+        }
+        let total = get_total_received(key, &ong());
+        let withdrawn: U128 = payees.iter().map(|p| get_withdrawn(key, p, &ong())).sum();
+        let claimable: U128 = payees.iter().map(|p| get_claimable(key, p, &ong())).sum();
+        let swept = get_dust_swept(key, &ong());
+        assert_eq!(get_balance(key, &ong()), total - withdrawn - swept);
+        assert_eq!(get_balance(key, &ong()), claimable + get_dust(key, &ong()));
+    }
+    // every token received is either with a payee, claimable, or dust of the designated address
+    let total = get_total_received(key, &ong());
+    let rp = get_register_param(key);
+    let entitled: U128 = rp
+        .addr_amt
+        .iter()
+        .map(|aa| get_withdrawn(key, &aa.to, &ong()) + get_claimable(key, &aa.to, &ong()))
+        .sum();
+    assert_eq!(entitled + rp.dust_of(total), total);
+}
+
+#[test]
+fn test_legacy_policy() {
+    let key: &[u8] = b"01";
+    let handle = build_runtime();
+    let payees = payees();
+    let legacy = LegacyRegisterParam {
+        addr_amt: payees
+            .iter()
+            .zip([3333, 3333, 3334].iter())
+            .map(|(to, percent)| LegacyAddrAmt {
+                to: to.clone(),
+                percent: *percent,
+                has_withdraw: to == &payees[0],
+            })
+            .collect(),
+        token_type: TokenType::ONG,
+        contract_addr: None,
+    };
+    database::put(generate_legacy_registry_param_key(key), legacy);
+    database::put(generate_legacy_balance_key(key), 300 as U128);
+
+    // the terms are migrated, the legacy balance waits for what the payees withdrew from it
     let rp = get_register_param(key);
-    assert!(rp.addr_amt[1].has_withdraw);
+    assert_eq!(rp.addr_amt.len(), 3);
+    assert_eq!(get_legacy_balance(key), 300);
+    assert_eq!(get_total_received(key, &ong()), 0);
+    assert_eq!(get_claimable(key, &payees[2], &ong()), 0);
+
+    // the first payee has withdrawn the share of the legacy balance, including the remainder
+    handle.witness(&[ADMIN.clone()]);
+    let withdrawals = vec![LegacyWithdrawal {
+        to: payees[0].clone(),
+        amount: 101,
+    }];
+    assert!(migrate_legacy_balance(key, withdrawals));
+    assert_eq!(get_total_received(key, &ong()), 300);
+    assert_eq!(get_withdrawn(key, &payees[0], &ong()), 101);
+    assert_eq!(get_balance(key, &ong()), 199);
+    assert_eq!(get_claimable(key, &payees[0], &ong()), 0);
+    assert_eq!(get_claimable(key, &payees[2], &ong()), 100);
+    assert!(database::get::<_, U128>(generate_legacy_balance_key(key)).is_none());
+
+    handle.witness(&[payees[1].clone()]);
+    handle.on_contract_call(|_addr: &Address, _data: &[u8]| -> Option<Vec<u8>> { Some(vec![1u8]) });
+    assert!(withdraw(key, &payees[1]));
+    assert_eq!(get_balance(key, &ong()), 100);
+    assert_eq!(get_payee_keys(&payees[2], 0, 10), vec![key.to_vec()]);
+}
+
+#[test]
+#[should_panic(expected = "withdrawn more than the share")]
+fn test_legacy_balance_overdrawn() {
+    let key: &[u8] = b"01";
+    let handle = build_runtime();
+    let payees = payees();
+    let legacy = LegacyRegisterParam {
+        addr_amt: payees
+            .iter()
+            .map(|to| LegacyAddrAmt {
+                to: to.clone(),
+                percent: 5000,
+                has_withdraw: true,
+            })
+            .take(2)
+            .collect(),
+        token_type: TokenType::ONG,
+        contract_addr: None,
+    };
+    database::put(generate_legacy_registry_param_key(key), legacy);
+    database::put(generate_legacy_balance_key(key), 300 as U128);
+
+    handle.witness(&[ADMIN.clone()]);
+    let withdrawals = vec![LegacyWithdrawal {
+        to: payees[1].clone(),
+        amount: 151,
+    }];
+    migrate_legacy_balance(key, withdrawals);
+}
+
+#[test]
+fn test_split_change() {
+    let key: &[u8] = b"01";
+    let handle = build_runtime();
+    let payees = payees();
+    handle.witness(&[payees[0].clone()]);
+    register_three(key, &payees, RemainderRule::FirstPayee);
+    let from = Address::repeat_byte(9);
+    handle.witness(&[from.clone()]);
+    handle.on_contract_call(|_addr: &Address, _data: &[u8]| -> Option<Vec<u8>> { Some(vec![1u8]) });
+    assert!(transfer(&from, key, &ong(), 100));
+
+    // payees[2] leaves, the others split half and half
+    let new_param = RegisterParam {
+        addr_amt: vec![
+            AddrAmt::new(payees[0].clone(), 5000),
+            AddrAmt::new(payees[1].clone(), 5000),
+        ],
+        ..RegisterParam::default()
+    };
+    let mut sink = Sink::new(64);
+    sink.write(&new_param);
+    handle.witness(&[payees[0].clone()]);
+    assert!(propose_split_change(key, &payees[0], sink.bytes()));
+    assert!(get_split_change(key).is_some());
+    assert_eq!(get_register_param(key).addr_amt.len(), 3);
+
+    handle.witness(&[payees[1].clone()]);
+    assert!(approve_split_change(key, &payees[1]));
+    assert!(get_split_change(key).is_none());
+    assert_eq!(get_register_param(key).addr_amt.len(), 2);
+
+    // what was received before the change is shared under the old terms
+    assert_eq!(get_claimable(key, &payees[0], &ong()), 34);
+    assert_eq!(get_claimable(key, &payees[1], &ong()), 33);
+    assert_eq!(get_claimable(key, &payees[2], &ong()), 33);
+
+    handle.witness(&[from.clone()]);
+    assert!(transfer(&from, key, &ong(), 100));
+    assert_eq!(get_claimable(key, &payees[0], &ong()), 84);
+    assert_eq!(get_claimable(key, &payees[1], &ong()), 83);
+    assert_eq!(get_claimable(key, &payees[2], &ong()), 33);
+
+    handle.witness(&[payees[2].clone()]);
+    assert!(withdraw(key, &payees[2]));
+    assert_eq!(get_balance(key, &ong()), 167);
+}
+
+#[test]
+#[should_panic]
+fn test_split_change_by_outsider() {
+    let key: &[u8] = b"01";
+    let handle = build_runtime();
+    let payees = payees();
+    handle.witness(&[payees[0].clone()]);
+    register_three(key, &payees, RemainderRule::FirstPayee);
+    let outsider = Address::repeat_byte(9);
+    let mut sink = Sink::new(64);
+    sink.write(&get_register_param(key));
+    handle.witness(&[outsider.clone()]);
+    propose_split_change(key, &outsider, sink.bytes());
+}
+
+#[test]
+fn test_nested_split() {
+    let handle = build_runtime();
+    let payees = payees();
+    handle.witness(&[payees[0].clone()]);
+    register_three(b"child", &payees, RemainderRule::FirstPayee);
+
+    let org = Address::repeat_byte(4);
+    let other = Address::repeat_byte(5);
+    let parent = half_and_half(&org, b"child", &other);
+    // the payees of the child consent to be nested
+    handle.witness(&[other.clone(), payees[0].clone(), payees[1].clone()]);
+    assert!(register(b"parent", &parent));
+
+    let effective = get_effective_split(b"parent");
+    assert_eq!(effective.len(), 4);
+    assert_eq!(effective[0].to, payees[0]);
+    assert_eq!(effective[0].percent, 1666);
+    assert_eq!(effective[3].to, other);
+    assert_eq!(effective[3].percent, 5000);
+
+    let from = Address::repeat_byte(9);
+    handle.witness(&[from.clone()]);
+    handle.on_contract_call(|_addr: &Address, _data: &[u8]| -> Option<Vec<u8>> { Some(vec![1u8]) });
+    assert!(transfer_withdraw(&from, b"parent", &ong(), 200));
+    assert_eq!(get_withdrawn(b"parent", &other, &ong()), 100);
+    assert_eq!(get_withdrawn(b"parent", &org, &ong()), 100);
+    assert_eq!(get_total_received(b"child", &ong()), 100);
+    assert_eq!(get_withdrawn(b"child", &payees[0], &ong()), 34);
+    assert_eq!(get_withdrawn(b"child", &payees[2], &ong()), 33);
+    assert_eq!(get_balance(b"parent", &ong()), 0);
+    assert_eq!(get_balance(b"child", &ong()), 0);
+}
+
+#[test]
+#[should_panic]
+fn test_nested_split_cycle() {
+    let handle = build_runtime();
+    let payees = payees();
+    handle.witness(&[payees[0].clone()]);
+    register_three(b"child", &payees, RemainderRule::FirstPayee);
+    let org = Address::repeat_byte(4);
+    handle.witness(&[org.clone(), payees[0].clone(), payees[1].clone()]);
+    assert!(register(b"parent", &half_and_half(&org, b"child", &payees[0])));
+
+    // the child can not be changed to forward into its parent
+    let cycle = half_and_half(&payees[0], b"parent", &payees[1]);
+    handle.witness(&[payees[0].clone(), payees[1].clone()]);
+    propose_split_change(b"child", &payees[0], &cycle);
+}
+
+#[test]
+#[should_panic(expected = "split policy nests too deep")]
+fn test_nested_split_too_deep_below_parents() {
+    let handle = build_runtime();
+    let payees = payees();
+    let org = Address::repeat_byte(4);
+    let other = Address::repeat_byte(5);
+    handle.witness(&[
+        payees[0].clone(),
+        payees[1].clone(),
+        org.clone(),
+        other.clone(),
+    ]);
+    register_three(b"child", &payees, RemainderRule::FirstPayee);
+    assert!(register(b"parent", &half_and_half(&org, b"child", &other)));
+    assert!(register(b"grand", &half_and_half(&org, b"parent", &other)));
+    register_three(b"leaf", &payees, RemainderRule::FirstPayee);
+    assert!(register(b"middle", &half_and_half(&org, b"leaf", &other)));
+
+    // two levels below the child are fine on their own, but not below its two parents
+    let deeper = half_and_half(&payees[0], b"middle", &payees[1]);
+    propose_split_change(b"child", &payees[0], &deeper);
+}
+
+#[test]
+#[should_panic(expected = "nested split policy did not consent")]
+fn test_nested_split_without_consent() {
+    let handle = build_runtime();
+    let payees = payees();
+    handle.witness(&[payees[0].clone()]);
+    register_three(b"child", &payees, RemainderRule::FirstPayee);
+
+    // only one third of the child signs, its amend threshold is a half
+    let org = Address::repeat_byte(4);
+    let other = Address::repeat_byte(5);
+    handle.witness(&[other.clone(), payees[0].clone()]);
+    register(b"parent", &half_and_half(&org, b"child", &other));
+}
+
+#[test]
+fn test_detach_parent() {
+    let handle = build_runtime();
+    handle.on_contract_call(|_addr: &Address, _data: &[u8]| -> Option<Vec<u8>> { Some(vec![1u8]) });
+    let payees = payees();
+    let org = Address::repeat_byte(4);
+    let other = Address::repeat_byte(5);
+    handle.witness(&[
+        payees[0].clone(),
+        payees[1].clone(),
+        org.clone(),
+        other.clone(),
+    ]);
+    register_three(b"child", &payees, RemainderRule::FirstPayee);
+    assert!(register(b"parent", &half_and_half(&org, b"child", &other)));
+    assert!(register(b"grand", &half_and_half(&org, b"parent", &other)));
+    assert!(register(b"great", &half_and_half(&org, b"grand", &other)));
+
+    // the child is too deep to nest anything, until it detaches from its parent
+    handle.witness(&[payees[0].clone(), payees[1].clone()]);
+    assert!(detach_parent(b"child", b"parent"));
+    assert!(get_register_param(b"parent").addr_amt[0].split.is_none());
+    register_three(b"leaf", &payees, RemainderRule::FirstPayee);
+    let deeper = half_and_half(&payees[0], b"leaf", &payees[1]);
+    assert!(propose_split_change(b"child", &payees[0], &deeper));
+    assert!(approve_split_change(b"child", &payees[1]));
+    assert_eq!(get_register_param(b"child").addr_amt.len(), 2);
+
+    // the share of the parent is paid to the payee directly
+    let from = Address::repeat_byte(9);
+    handle.witness(&[from.clone()]);
+    assert!(transfer_withdraw(&from, b"parent", &ong(), 200));
+    assert_eq!(get_withdrawn(b"parent", &org, &ong()), 100);
+    assert_eq!(get_total_received(b"child", &ong()), 0);
+}
+
+#[test]
+#[should_panic(expected = "not enough payee signatures")]
+fn test_detach_parent_by_others() {
+    let handle = build_runtime();
+    let payees = payees();
+    let org = Address::repeat_byte(4);
+    let other = Address::repeat_byte(5);
+    handle.witness(&[payees[0].clone(), payees[1].clone(), other.clone()]);
+    register_three(b"child", &payees, RemainderRule::FirstPayee);
+    assert!(register(b"parent", &half_and_half(&org, b"child", &other)));
+
+    handle.witness(&[org.clone(), other.clone()]);
+    detach_parent(b"child", b"parent");
+}
+
+#[test]
+fn test_external_split_accrues() {
+    let handle = build_runtime();
+    let payees = payees();
+    let nested_contract = Address::repeat_byte(7);
+    let mut rp = RegisterParam::default();
+    rp.addr_amt = vec![
+        AddrAmt {
+            split: Some(SplitRef {
+                contract: Some(nested_contract.clone()),
+                key: b"nested".to_vec(),
+            }),
+            ..AddrAmt::new(payees[0].clone(), 5000)
+        },
+        AddrAmt::new(payees[1].clone(), 5000),
+    ];
+    let mut sink = Sink::new(64);
+    sink.write(rp);
+    let nested = nested_contract.clone();
+    handle.on_contract_call(move |addr: &Address, data: &[u8]| -> Option<Vec<u8>> {
+        if addr == &nested {
+            let mut source = Source::new(data);
+            let method: &[u8] = source.read().unwrap();
+            assert_eq!(method, b"get_register_param");
+            let mut sink = Sink::new(64);
+            sink.write(RegisterParam::default());
+            return Some(sink.bytes().to_vec());
+        }
+        Some(vec![1u8])
+    });
+    handle.witness(&[payees[0].clone()]);
+    assert!(register(b"01", sink.bytes()));
+
+    // the external share stays in the contract when the purchase is distributed
+    let from = Address::repeat_byte(9);
+    handle.witness(&[from.clone()]);
+    assert!(transfer_withdraw(&from, b"01", &ong(), 100));
+    assert_eq!(get_withdrawn(b"01", &payees[1], &ong()), 50);
+    assert_eq!(get_withdrawn(b"01", &payees[0], &ong()), 0);
+    assert_eq!(get_claimable(b"01", &payees[0], &ong()), 50);
+
+    // and is forwarded on its own, without distributing it further there
+    handle.on_contract_call(move |addr: &Address, data: &[u8]| -> Option<Vec<u8>> {
+        if addr == &nested_contract {
+            let mut source = Source::new(data);
+            let method: &[u8] = source.read().unwrap();
+            assert_eq!(method, b"transferCurrency");
+        }
+        Some(vec![1u8])
+    });
+    assert!(forward_external_share(b"01", &payees[0], &ong()));
+    assert_eq!(get_withdrawn(b"01", &payees[0], &ong()), 50);
+    assert_eq!(get_balance(b"01", &ong()), 0);
+}
+
+#[test]
+#[should_panic(expected = "nested split policy not registered")]
+fn test_external_split_not_registered() {
+    let handle = build_runtime();
+    let payees = payees();
+    let mut rp = RegisterParam::default();
+    rp.addr_amt = vec![AddrAmt {
+        split: Some(SplitRef {
+            contract: Some(Address::repeat_byte(7)),
+            key: b"nested".to_vec(),
+        }),
+        ..AddrAmt::new(payees[0].clone(), TOTAL)
+    }];
+    let mut sink = Sink::new(64);
+    sink.write(rp);
+    handle.on_contract_call(|_addr: &Address, _data: &[u8]| -> Option<Vec<u8>> { None });
+    handle.witness(&[payees[0].clone()]);
+    register(b"01", sink.bytes());
+}
+
+#[test]
+fn test_vesting() {
+    let handle = build_runtime();
+    handle.on_contract_call(|_addr: &Address, _data: &[u8]| -> Option<Vec<u8>> { Some(vec![1u8]) });
+    let owner = Address::repeat_byte(1);
+    let contributor = Address::repeat_byte(2);
+    let rp = RegisterParam {
+        addr_amt: vec![
+            AddrAmt::new(owner.clone(), 5000),
+            AddrAmt {
+                vesting: Some(Vesting {
+                    start: 0,
+                    cliff: 10,
+                    duration: 100,
+                }),
+                ..AddrAmt::new(contributor.clone(), 5000)
+            },
+        ],
+        owner: Some(owner.clone()),
+        ..RegisterParam::default()
+    };
+    let mut sink = Sink::new(64);
+    sink.write(rp);
+    let key = b"vest";
+    handle.witness(&[owner.clone()]);
+    handle.timestamp(5);
+    assert!(register(key, sink.bytes()));
+    let from = Address::repeat_byte(4);
+    handle.witness(&[from.clone()]);
+    assert!(transfer(&from, key, &ong(), 1000));
+
+    // before the cliff
+    assert_eq!(get_claimable(key, &contributor, &ong()), 0);
+    assert_eq!(get_claimable(key, &owner, &ong()), 500);
+
+    handle.timestamp(50);
+    let info = get_vesting(key, &contributor, &ong());
+    assert_eq!(info.entitled, 500);
+    assert_eq!(info.vested, 250);
+    assert_eq!(info.unvested, 250);
+    handle.witness(&[contributor.clone()]);
+    assert!(withdraw(key, &contributor));
+    assert_eq!(get_withdrawn(key, &contributor, &ong()), 250);
+
+    // the unvested half goes to the owner
+    handle.witness(&[owner.clone()]);
+    assert!(revoke_vesting(key, &contributor));
+    assert_eq!(get_claimable(key, &contributor, &ong()), 0);
+    assert_eq!(get_claimable(key, &owner, &ong()), 750);
+    let param = get_register_param(key);
+    assert_eq!(param.addr_amt.len(), 1);
+    assert_eq!(param.addr_amt[0].percent, TOTAL);
+
+    handle.witness(&[from.clone()]);
+    assert!(transfer(&from, key, &ong(), 100));
+    assert_eq!(get_claimable(key, &owner, &ong()), 850);
+    assert_eq!(get_balance(key, &ong()), 850);
+}
+
+#[test]
+fn test_distribute_threshold() {
+    let handle = build_runtime();
+    handle.on_contract_call(|_addr: &Address, _data: &[u8]| -> Option<Vec<u8>> { Some(vec![1u8]) });
+    let payees = payees();
+    let rp = RegisterParam {
+        addr_amt: vec![
+            AddrAmt::new(payees[0].clone(), 9000),
+            AddrAmt {
+                min_payout: 50,
+                ..AddrAmt::new(payees[1].clone(), 1000)
+            },
+        ],
+        auto_distribute: Some(300),
+        ..RegisterParam::default()
+    };
+    let mut sink = Sink::new(64);
+    sink.write(rp);
+    let key = b"01";
+    handle.witness(&[payees[0].clone()]);
+    assert!(register(key, sink.bytes()));
+
+    // below the pool threshold the sales accrue, the share of the second payee below the
+    // minimum payout does not count
+    let from = Address::repeat_byte(4);
+    handle.witness(&[from.clone()]);
+    for _ in 0..3 {
+        assert!(transfer_withdraw(&from, key, &ong(), 100));
+    }
+    assert_eq!(get_balance(key, &ong()), 300);
+    assert_eq!(get_withdrawn(key, &payees[0], &ong()), 0);
+
+    // what can be paid out crosses the threshold, the second payee is below the minimum payout
+    assert!(transfer_withdraw(&from, key, &ong(), 100));
+    assert_eq!(get_withdrawn(key, &payees[0], &ong()), 360);
+    assert_eq!(get_withdrawn(key, &payees[1], &ong()), 0);
+    assert_eq!(get_balance(key, &ong()), 40);
+
+    // the accrued share alone does not trigger another distribution
+    assert!(transfer_withdraw(&from, key, &ong(), 10));
+    assert_eq!(get_withdrawn(key, &payees[0], &ong()), 360);
+
+    assert!(transfer(&from, key, &ong(), 300));
+    assert!(distribute(key));
+    assert_eq!(get_withdrawn(key, &payees[0], &ong()), 639);
+    assert_eq!(get_withdrawn(key, &payees[1], &ong()), 71);
+    assert_eq!(get_balance(key, &ong()), 0);
+}
+
+#[test]
+#[should_panic]
+fn test_distribute_below_threshold() {
+    let handle = build_runtime();
+    handle.on_contract_call(|_addr: &Address, _data: &[u8]| -> Option<Vec<u8>> { Some(vec![1u8]) });
+    let payees = payees();
+    let mut rp = RegisterParam::default();
+    rp.addr_amt.push(AddrAmt::new(payees[0].clone(), TOTAL));
+    rp.auto_distribute = Some(300);
+    let mut sink = Sink::new(64);
+    sink.write(rp);
+    handle.witness(&[payees[0].clone()]);
+    assert!(register(b"01", sink.bytes()));
+    assert!(transfer(&payees[0], b"01", &ong(), 100));
+    distribute(b"01");
+}
+
+#[test]
+#[should_panic]
+fn test_revoke_vesting_by_payee() {
+    let handle = build_runtime();
+    let payees = payees();
+    handle.witness(&[payees[0].clone()]);
+    register_three(b"01", &payees, RemainderRule::FirstPayee);
+    revoke_vesting(b"01", &payees[1]);
+}
+
+#[test]
+fn test_multi_currency() {
+    let handle = build_runtime();
+    handle.on_contract_call(|_addr: &Address, _data: &[u8]| -> Option<Vec<u8>> { Some(vec![1u8]) });
+    let payees = payees();
+    let stable = Currency {
+        contract_addr: Address::repeat_byte(9),
+        contract_type: TokenType::OEP4,
+    };
+    let mut rp = RegisterParam::default();
+    for (i, payee) in payees[..2].iter().enumerate() {
+        rp.addr_amt.push(AddrAmt::new(
+            payee.clone(),
+            if i == 0 { 6000 } else { 4000 },
+        ));
+    }
+    rp.currencies.push(stable.clone());
+    let mut sink = Sink::new(64);
+    sink.write(rp);
+    let key = b"01";
+    handle.witness(&[payees[0].clone(), payees[1].clone()]);
+    assert!(register(key, sink.bytes()));
+
+    assert!(transfer(&payees[0], key, &ong(), 100));
+    assert!(transfer(&payees[0], key, &stable, 1000));
+    let balances = get_balances(key);
+    assert_eq!(balances.len(), 2);
+    assert_eq!(balances[0].amount, 100);
+    assert_eq!(balances[1].amount, 1000);
+
+    assert!(withdraw_currency(key, &payees[0], &stable));
+    assert_eq!(get_withdrawn(key, &payees[0], &stable), 600);
+    assert_eq!(get_withdrawn(key, &payees[0], &ong()), 0);
+    let claimables = get_claimables(key, &payees[1]);
+    assert_eq!(claimables[0].amount, 40);
+    assert_eq!(claimables[1].amount, 400);
+
+    assert!(withdraw(key, &payees[1]));
+    assert_eq!(get_balance(key, &ong()), 60);
+    assert_eq!(get_balance(key, &stable), 0);
+}
+
+#[test]
+#[should_panic]
+fn test_currency_not_accepted() {
+    let handle = build_runtime();
+    handle.on_contract_call(|_addr: &Address, _data: &[u8]| -> Option<Vec<u8>> { Some(vec![1u8]) });
+    let payees = payees();
+    handle.witness(&[payees[0].clone()]);
+    register_three(b"01", &payees, RemainderRule::FirstPayee);
+    let ont = Currency {
+        contract_addr: Address::new([0u8; 20]),
+        contract_type: TokenType::ONT,
+    };
+    transfer(&payees[0], b"01", &ont, 100);
+}
+
+#[test]
+fn test_native_currency_alias() {
+    let handle = build_runtime();
+    handle.on_contract_call(|_addr: &Address, _data: &[u8]| -> Option<Vec<u8>> { Some(vec![1u8]) });
+    let payees = payees();
+    handle.witness(&[payees[0].clone()]);
+    register_three(b"01", &payees, RemainderRule::FirstPayee);
+    // the contract_addr of a native currency does not matter
+    let alias = Currency {
+        contract_addr: Address::repeat_byte(7),
+        contract_type: TokenType::ONG,
+    };
+    assert!(transfer_withdraw(&payees[0], b"01", &alias, 100));
+    assert_eq!(get_total_received(b"01", &ong()), 100);
+    assert_eq!(get_withdrawn(b"01", &payees[0], &ong()), 34);
+    assert_eq!(get_balance(b"01", &alias), 0);
+}
+
+#[test]
+#[should_panic]
+fn test_duplicate_native_currency() {
+    let handle = build_runtime();
+    let payees = payees();
+    let mut rp = RegisterParam::default();
+    rp.addr_amt.push(AddrAmt::new(payees[0].clone(), TOTAL));
+    rp.currencies.push(Currency {
+        contract_addr: Address::repeat_byte(7),
+        contract_type: TokenType::ONG,
+    });
+    let mut sink = Sink::new(64);
+    sink.write(rp);
+    handle.witness(&[payees[0].clone()]);
+    register(b"01", sink.bytes());
+}
+
+#[test]
+#[should_panic(expected = "unauthorized payer")]
+fn test_transfer_without_witness() {
+    let handle = build_runtime();
+    handle.on_contract_call(|_addr: &Address, _data: &[u8]| -> Option<Vec<u8>> { Some(vec![1u8]) });
+    let payees = payees();
+    handle.witness(&[payees[0].clone()]);
+    register_three(b"01", &payees, RemainderRule::FirstPayee);
+    let from = Address::repeat_byte(4);
+    transfer_withdraw(&from, b"01", &ong(), 100);
+}
+
+#[test]
+fn test_transfer_by_allowed_caller() {
+    let handle = build_runtime();
+    handle.on_contract_call(|_addr: &Address, _data: &[u8]| -> Option<Vec<u8>> { Some(vec![1u8]) });
+    let payees = payees();
+    handle.witness(&[payees[0].clone()]);
+    register_three(b"01", &payees, RemainderRule::FirstPayee);
+    let ddxf = Address::repeat_byte(8);
+    handle.witness(&[ADMIN.clone()]);
+    assert!(add_allowed_caller(&ddxf));
+
+    // the buyer does not sign, the allowed contract pays on behalf of the buyer
+    handle.caller(&ddxf);
+    let buyer = Address::repeat_byte(4);
+    assert!(transfer_withdraw(&buyer, b"01", &ong(), 100));
+    assert_eq!(get_withdrawn(b"01", &payees[0], &ong()), 34);
+}
+
+#[test]
+#[should_panic(expected = "split policy not registered")]
+fn test_transfer_unknown_key() {
+    let handle = build_runtime();
+    handle.on_contract_call(|_addr: &Address, _data: &[u8]| -> Option<Vec<u8>> { Some(vec![1u8]) });
+    let from = Address::repeat_byte(4);
+    handle.witness(&[from.clone()]);
+    transfer(&from, b"unknown", &ong(), 100);
+}
+
+#[test]
+#[should_panic(expected = "split policy not registered")]
+fn test_withdraw_unknown_key() {
+    let handle = build_runtime();
+    let payees = payees();
+    handle.witness(&[payees[0].clone()]);
+    withdraw(b"unknown", &payees[0]);
+}
+
+#[test]
+fn test_template() {
+    let handle = build_runtime();
+    handle.on_contract_call(|_addr: &Address, _data: &[u8]| -> Option<Vec<u8>> { Some(vec![1u8]) });
+    let payees = payees();
+    let seller = Address::repeat_byte(5);
+    handle.witness(&[seller.clone()]);
+    let template_id = b"tpl";
+    assert!(register_template(
+        template_id,
+        &seller,
+        &three_way(&payees, RemainderRule::FirstPayee)
+    ));
+    assert!(bind_template(b"a", template_id, false));
+    assert!(bind_template(b"b", template_id, true));
+    assert_eq!(
+        get_template_resources(template_id, 0, 10),
+        vec![b"a".to_vec(), b"b".to_vec()]
+    );
+    assert_eq!(
+        get_template_resources(template_id, 1, 1),
+        vec![b"b".to_vec()]
+    );
+
+    let from = Address::repeat_byte(4);
+    handle.witness(&[from.clone()]);
+    assert!(transfer(&from, b"a", &ong(), 100));
+    assert!(transfer(&from, b"b", &ong(), 100));
+
+    // the new terms leave the third payee out
+    let mut rp = RegisterParam::default();
+    for payee in payees[..2].iter() {
+        rp.addr_amt.push(AddrAmt::new(payee.clone(), 5000));
+    }
+    let mut sink = Sink::new(64);
+    sink.write(rp);
+    handle.witness(&[seller.clone()]);
+    assert!(update_template(template_id, sink.bytes()));
+
+    handle.witness(&[from.clone()]);
+    assert!(transfer(&from, b"a", &ong(), 100));
+    assert!(transfer(&from, b"b", &ong(), 100));
+    assert_eq!(get_claimable(b"a", &payees[0], &ong()), 84);
+    assert_eq!(get_claimable(b"a", &payees[2], &ong()), 33);
+    assert_eq!(get_claimable(b"b", &payees[0], &ong()), 68);
+    assert_eq!(get_claimable(b"b", &payees[2], &ong()), 66);
+}
+
+#[test]
+fn test_template_updated_twice() {
+    let handle = build_runtime();
+    handle.on_contract_call(|_addr: &Address, _data: &[u8]| -> Option<Vec<u8>> { Some(vec![1u8]) });
+    let payees = payees();
+    let seller = Address::repeat_byte(5);
+    handle.witness(&[seller.clone()]);
+    let template_id = b"tpl";
+    assert!(register_template(
+        template_id,
+        &seller,
+        &three_way(&payees, RemainderRule::FirstPayee)
+    ));
+    assert!(bind_template(b"a", template_id, false));
+    let from = Address::repeat_byte(4);
+    handle.witness(&[from.clone()]);
+    assert!(transfer(&from, b"a", &ong(), 100));
+
+    // the key is not used between the updates, the sale is settled under the first terms
+    handle.witness(&[seller.clone()]);
+    let mut rp = RegisterParam::default();
+    for payee in payees[..2].iter() {
+        rp.addr_amt.push(AddrAmt::new(payee.clone(), 5000));
+    }
+    let mut sink = Sink::new(64);
+    sink.write(rp);
+    assert!(update_template(template_id, sink.bytes()));
+    let mut rp = RegisterParam::default();
+    rp.addr_amt.push(AddrAmt::new(payees[1].clone(), TOTAL));
+    let mut sink = Sink::new(64);
+    sink.write(rp);
+    assert!(update_template(template_id, sink.bytes()));
+
+    handle.witness(&[from.clone()]);
+    assert!(transfer(&from, b"a", &ong(), 100));
+    assert_eq!(get_claimable(b"a", &payees[0], &ong()), 34);
+    assert_eq!(get_claimable(b"a", &payees[1], &ong()), 133);
+    assert_eq!(get_claimable(b"a", &payees[2], &ong()), 33);
+}
+
+#[test]
+#[should_panic(expected = "split policy follows a template")]
+fn test_amend_template_key() {
+    let handle = build_runtime();
+    let payees = payees();
+    handle.witness(&[payees[0].clone()]);
+    let three = three_way(&payees, RemainderRule::FirstPayee);
+    assert!(register_template(b"tpl", &payees[0], &three));
+    assert!(bind_template(b"a", b"tpl", false));
+    propose_split_change(b"a", &payees[0], &three);
+}
+
+#[test]
+fn test_payee_change() {
+    let handle = build_runtime();
+    handle.on_contract_call(|_addr: &Address, _data: &[u8]| -> Option<Vec<u8>> { Some(vec![1u8]) });
+    let payees = payees();
+    handle.witness(&[payees[0].clone()]);
+    register_three(b"01", &payees, RemainderRule::FirstPayee);
+    register_three(b"02", &payees, RemainderRule::FirstPayee);
+    let from = Address::repeat_byte(4);
+    handle.witness(&[from.clone()]);
+    assert!(transfer(&from, b"01", &ong(), 100));
+    assert!(transfer(&from, b"02", &ong(), 200));
+
+    handle.witness(&[payees[0].clone()]);
+    assert!(withdraw(b"01", &payees[0]));
+    let statement = get_statement(&payees[0], 0, 10);
+    assert_eq!(statement.len(), 2);
+    assert_eq!(statement[0].key, b"01".to_vec());
+    assert_eq!(statement[0].received, 34);
+    assert_eq!(statement[0].withdrawn, 34);
+    assert_eq!(statement[0].claimable, 0);
+    assert_eq!(statement[1].received, 68);
+    assert_eq!(statement[1].claimable, 68);
+
+    // the new address takes over the entry
+    let rotated = Address::repeat_byte(6);
+    assert!(request_payee_change(b"01", &payees[0], &rotated));
+    assert_eq!(get_register_param(b"01").addr_amt[0].to, rotated);
+    assert_eq!(get_payee_keys(&payees[0], 0, 10), vec![b"02".to_vec()]);
+    // the emptied entry keeps its position
+    assert_eq!(get_payee_key_count(&payees[0]), 2);
+    assert_eq!(get_payee_keys(&payees[0], 1, 1), vec![b"02".to_vec()]);
+    handle.witness(&[from.clone()]);
+    assert!(transfer(&from, b"01", &ong(), 100));
+    assert_eq!(get_claimable(b"01", &rotated, &ong()), 34);
+    assert_eq!(get_claimable(b"01", &payees[0], &ong()), 0);
+    let statement = get_statement(&rotated, 0, 10);
+    assert_eq!(statement.len(), 1);
+    assert_eq!(statement[0].received, 68);
+    assert_eq!(statement[0].withdrawn, 34);
+}
+
+#[test]
+fn test_payee_change_discards_split_change() {
+    let handle = build_runtime();
+    let payees = payees();
+    handle.witness(&[payees[0].clone()]);
+    register_three(b"01", &payees, RemainderRule::FirstPayee);
+    let new_param = RegisterParam {
+        addr_amt: vec![
+            AddrAmt::new(payees[0].clone(), 5000),
+            AddrAmt::new(payees[1].clone(), 5000),
+        ],
+        ..RegisterParam::default()
+    };
+    let mut sink = Sink::new(64);
+    sink.write(&new_param);
+    assert!(propose_split_change(b"01", &payees[0], sink.bytes()));
+    assert!(get_split_change(b"01").is_some());
+
+    let rotated = Address::repeat_byte(6);
+    assert!(request_payee_change(b"01", &payees[0], &rotated));
+    assert!(get_split_change(b"01").is_none());
+}
+
+#[test]
+fn test_payee_change_delay() {
+    let handle = build_runtime();
+    let payees = payees();
+    let mut rp = RegisterParam::default();
+    rp.addr_amt.push(AddrAmt::new(payees[0].clone(), TOTAL));
+    rp.reassign_delay = 100;
+    let mut sink = Sink::new(64);
+    sink.write(rp);
+    handle.witness(&[payees[0].clone()]);
+    handle.timestamp(10);
+    assert!(register(b"01", sink.bytes()));
+
+    assert!(request_payee_change(b"01", &payees[0], &payees[1]));
+    assert_eq!(get_register_param(b"01").addr_amt[0].to, payees[0]);
+    assert_eq!(
+        get_payee_change(b"01", &payees[0]).unwrap().effective_at,
+        110
+    );
+    handle.timestamp(110);
+    handle.witness(&[]);
+    assert!(confirm_payee_change(b"01", &payees[0]));
+    assert_eq!(get_register_param(b"01").addr_amt[0].to, payees[1]);
+}
+
+#[test]
+#[should_panic(expected = "payee change is not effective yet")]
+fn test_payee_change_too_early() {
+    let handle = build_runtime();
+    let payees = payees();
+    let mut rp = RegisterParam::default();
+    rp.addr_amt.push(AddrAmt::new(payees[0].clone(), TOTAL));
+    rp.reassign_delay = 100;
+    let mut sink = Sink::new(64);
+    sink.write(rp);
+    handle.witness(&[payees[0].clone()]);
+    handle.timestamp(10);
+    assert!(register(b"01", sink.bytes()));
+    assert!(request_payee_change(b"01", &payees[0], &payees[1]));
+    confirm_payee_change(b"01", &payees[0]);
+}
+
+/// `org` forwards its half into the `nested` policy of this contract
+fn half_and_half(org: &Address, nested: &[u8], other: &Address) -> Vec<u8> {
+    let rp = RegisterParam {
+        addr_amt: vec![
+            AddrAmt {
+                split: Some(SplitRef {
+                    contract: None,
+                    key: nested.to_vec(),
+                }),
+                ..AddrAmt::new(org.clone(), 5000)
+            },
+            AddrAmt::new(other.clone(), 5000),
+        ],
+        amend_threshold: 5000,
+        ..RegisterParam::default()
+    };
+    let mut sink = Sink::new(64);
+    sink.write(rp);
+    sink.bytes().to_vec()
+}
+
+fn ong() -> Currency {
+    Currency {
+        contract_addr: Address::new([0u8; 20]),
+        contract_type: TokenType::ONG,
+    }
+}
+
+fn payees() -> Vec<Address> {
+    vec![
+        Address::repeat_byte(1),
+        Address::repeat_byte(2),
+        Address::repeat_byte(3),
+    ]
+}
+
+/// register a 3333/3333/3334 split, need the first payee signature
+fn register_three(key: &[u8], payees: &[Address], remainder: RemainderRule) {
+    assert!(register(key, &three_way(payees, remainder)));
+}
+
+/// a 3333/3333/3334 split
+fn three_way(payees: &[Address], remainder: RemainderRule) -> Vec<u8> {
+    let rp = RegisterParam {
+        addr_amt: vec![
+            AddrAmt::new(payees[0].clone(), 3333),
+            AddrAmt::new(payees[1].clone(), 3333),
+            AddrAmt::new(payees[2].clone(), 3334),
+        ],
+        remainder,
+        amend_threshold: 5000,
+        ..RegisterParam::default()
+    };
+    let mut sink = Sink::new(64);
+    sink.write(rp);
+    sink.bytes().to_vec()
 }